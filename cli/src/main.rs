@@ -1,62 +1,186 @@
-use clap::{Parser, Subcommand};
-use one_two_pay_api::{Bank, Client, QueryReq, TransferReq};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use one_two_pay_api::batch::BatchError;
+use one_two_pay_api::error::ApiError;
+use one_two_pay_api::fields::FieldError;
+use one_two_pay_api::guard::{Limit, LimitAction};
+use one_two_pay_api::payout::LargeAmountPolicy;
+use one_two_pay_api::qr::SlipQr;
+use one_two_pay_api::reconcile::ReconcileError;
+use one_two_pay_api::schema::SchemaType;
+use one_two_pay_api::{
+    Bank, BankAccount, BatchRunner, Client, DuplicateDetector, FileJournal, FileLimitStore,
+    LimitGuard, LimitStore, Limits, MemoryLimitStore, PayoutOrchestrator, QueryReq, Reconciler,
+    Ref1, Ref1Generator, RetryPolicy, ThaiMobile, Thb, TransferReq, ONE_TWO_PAY_URL,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Args)]
+struct ConnectionArgs {
+    /// Required by commands that call the API
     #[arg(short, long, env = "API_KEY", hide_env_values = true)]
-    api_key: String,
+    api_key: Option<String>,
 
+    /// Required by commands that call the API
     #[arg(short, long, env = "PARTNER_CODE")]
-    partner_code: String,
+    partner_code: Option<String>,
 
+    /// Required by commands that call the API
     #[arg(short, long, env = "CHANNEL")]
-    channel: String,
+    channel: Option<String>,
 
-    #[command(subcommand)]
-    command: Commands,
+    /// Base URL of the API, e.g. address of a local mock server
+    #[arg(long, env = "BASE_URL", default_value = ONE_TWO_PAY_URL)]
+    base_url: String,
+
+    /// Timeout of a single request in seconds
+    #[arg(long, env = "TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Total attempts for network failures and temporary API errors, 1 disables retries
+    #[arg(long, env = "MAX_ATTEMPTS", default_value_t = 1)]
+    max_attempts: u32,
+
+    /// Refuse a transfer of the same amount to the same account as another one
+    /// made within this many seconds, unless --allow-duplicate is given
+    #[arg(long, env = "DUPLICATE_WINDOW")]
+    duplicate_window: Option<u64>,
+
+    /// Status the API answers when a queried ref1 doesn't exist. It is not
+    /// documented, without it a transfer that may have been sent is never re-sent.
+    #[arg(long, env = "NOT_FOUND_CODE", allow_hyphen_values = true)]
+    not_found_code: Option<i32>,
+
+    #[command(flatten, next_help_heading = "Limits")]
+    limits: LimitArgs,
+}
+
+/// Client side limits per Bangkok day, checked before a transfer is sent
+#[derive(Args)]
+struct LimitArgs {
+    /// Max total THB of all transfers in a day
+    #[arg(long, env = "DAILY_LIMIT")]
+    daily_limit: Option<Thb>,
+    /// Max number of all transfers in a day
+    #[arg(long, env = "DAILY_COUNT")]
+    daily_count: Option<u32>,
+    /// Max total THB of the partner code in a day
+    #[arg(long)]
+    partner_limit: Option<Thb>,
+    /// Max number of transfers of the partner code in a day
+    #[arg(long)]
+    partner_count: Option<u32>,
+    /// Max total THB to one bank account in a day
+    #[arg(long)]
+    recipient_limit: Option<Thb>,
+    /// Max number of transfers to one bank account in a day
+    #[arg(long)]
+    recipient_count: Option<u32>,
+    /// File where transfers are counted across runs. Without it only transfers of
+    /// this run are counted
+    #[arg(long, env = "LIMIT_STORE")]
+    limit_store: Option<PathBuf>,
+    /// Wait up to this many seconds for limits to reset at midnight instead of
+    /// rejecting the transfer
+    #[arg(long)]
+    limit_wait: Option<u64>,
+}
+
+impl LimitArgs {
+    fn guard(&self) -> Result<Option<LimitGuard>, Box<dyn std::error::Error>> {
+        let limit = |amount, count| Limit { amount, count };
+        let limits = Limits {
+            daily: limit(self.daily_limit, self.daily_count),
+            per_partner: limit(self.partner_limit, self.partner_count),
+            per_recipient: limit(self.recipient_limit, self.recipient_count),
+        };
+        if limits == Limits::default() {
+            return Ok(None);
+        }
+        let store: Arc<dyn LimitStore> = match &self.limit_store {
+            Some(path) => Arc::new(FileLimitStore::open(path)?),
+            None => Arc::new(MemoryLimitStore::new()),
+        };
+        let action = match self.limit_wait {
+            Some(secs) => LimitAction::Queue {
+                max_wait: Duration::from_secs(secs),
+            },
+            None => LimitAction::Reject,
+        };
+        Ok(Some(LimitGuard::new(limits, store).action(action)))
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Make transfer to bank account
-    Transfer {
-        /// Bank account number
-        #[arg(long)]
-        bankacc: String,
-        /// Which bank to transfer to
-        #[arg(long, value_enum)]
-        bank: Bank,
-        /// Amount of money to transfer
-        #[arg(long)]
-        amount: f64,
-        /// Account name, owner of the bank account
-        #[arg(long)]
-        accname: String,
-        /// Mobile number in Thai local format
-        #[arg(long)]
-        mobileno: String,
-        /// Reference by which system was made the transaction
-        #[arg(long)]
-        transaction_by: String,
-        /// External ID of transaction. Must have length => 1 and <= 30.
-        #[arg(long)]
-        ref1: String,
-        /// Additional data
-        #[arg(long)]
-        ref2: Option<String>,
-        /// Additional data
-        #[arg(long)]
-        ref3: Option<String>,
-        /// Additional data
+    Transfer(TransferArgs),
+    /// Make transfer and wait until it is settled, failed or needs manual review
+    Payout {
+        #[command(flatten)]
+        transfer: TransferArgs,
+        /// Give up waiting for the final state after this many seconds
+        #[arg(long, default_value_t = 600)]
+        deadline: u64,
+        /// Record the payout in this journal file. Payouts it has in flight are
+        /// queried first and nothing is sent while some are not final.
+        #[arg(long, env = "JOURNAL")]
+        journal: Option<PathBuf>,
+        /// What to do with payouts above the 100,000 THB manual review threshold
+        #[arg(long, value_enum, default_value_t = LargeAmountPolicy::Track)]
+        large_amount: LargeAmountPolicy,
+    },
+    /// Pay every row of a CSV or JSON lines file, columns are named like the
+    /// options of `transfer`. Running it again skips rows that are settled and
+    /// queries rows whose outcome was unclear instead of sending them again.
+    Batch {
+        /// Rows to pay, .csv or .jsonl
         #[arg(long)]
-        ref4: Option<String>,
-        /// Meaning is unknown
+        input: PathBuf,
+        /// Result of every row is appended to this JSON lines file
         #[arg(long)]
-        line_token: Option<String>,
-        /// Meaning is unknown alas that is email address
+        results: PathBuf,
+        /// How many payouts run at the same time
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Give up waiting for the final state of a row after this many seconds
+        #[arg(long, default_value_t = 600)]
+        deadline: u64,
+        /// Record the payouts in this journal file. Payouts it has in flight are
+        /// queried first and nothing is sent while some are not final.
+        #[arg(long, env = "JOURNAL")]
+        journal: Option<PathBuf>,
+        /// What to do with payouts above the 100,000 THB manual review threshold
+        #[arg(long, value_enum, default_value_t = LargeAmountPolicy::Track)]
+        large_amount: LargeAmountPolicy,
+    },
+    /// Check payouts that the journal has in flight, e.g. after a crash
+    Recover {
+        /// Journal file written by `payout --journal`
+        #[arg(long, env = "JOURNAL")]
+        journal: PathBuf,
+    },
+    /// Compare payouts of our ledger with the provider's records
+    Reconcile {
+        /// Export of the ledger with ref1, amount, bankacc and bank columns,
+        /// .csv or .jsonl
         #[arg(long)]
-        email: Option<String>,
+        input: PathBuf,
+        /// How many queries run at the same time
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
     },
     /// Querying status of payment
     Inquery {
@@ -64,50 +188,238 @@ enum Commands {
         #[arg(short, long)]
         ref1: String,
     },
+    /// Generate a new unique ref1, or print when a generated ref1 was made
+    Ref1 {
+        /// Prefix of the new value, up to 10 characters. Example: pay-
+        #[arg(long, conflicts_with = "decode")]
+        prefix: Option<String>,
+        /// Print creation time of this generated ref1 instead
+        #[arg(long)]
+        decode: Option<Ref1>,
+    },
+    /// Decode the qrstring of a transfer response and verify its checksum
+    Slip { qrstring: SlipQr },
+    /// Print JSON schema of a request or response type
+    Schema {
+        #[arg(value_enum)]
+        schema_type: SchemaType,
+    },
+}
+
+#[derive(Args)]
+struct TransferArgs {
+    /// Bank account number
+    #[arg(long)]
+    bankacc: String,
+    /// Which bank to transfer to
+    #[arg(long, value_enum)]
+    bank: Bank,
+    /// Amount of THB to transfer. Example: 1000.50
+    #[arg(long)]
+    amount: Thb,
+    /// Account name, owner of the bank account
+    #[arg(long)]
+    accname: String,
+    /// Thai mobile number, e.g. 0805933181 or +66 80 593 3181
+    #[arg(long)]
+    mobileno: ThaiMobile,
+    /// Reference by which system was made the transaction
+    #[arg(long)]
+    transaction_by: String,
+    /// External ID of transaction. Must have length => 1 and <= 30.
+    #[arg(long)]
+    ref1: Ref1,
+    /// Additional data
+    #[arg(long)]
+    ref2: Option<String>,
+    /// Additional data
+    #[arg(long)]
+    ref3: Option<String>,
+    /// Additional data
+    #[arg(long)]
+    ref4: Option<String>,
+    /// Meaning is unknown
+    #[arg(long)]
+    line_token: Option<String>,
+    /// Meaning is unknown alas that is email address
+    #[arg(long)]
+    email: Option<String>,
+    /// Send even if the same amount went to the same account recently
+    #[arg(long)]
+    allow_duplicate: bool,
+}
+
+impl TransferArgs {
+    fn into_request(self) -> Result<TransferReq, FieldError> {
+        Ok(TransferReq {
+            bankacc: BankAccount::new(self.bank, &self.bankacc)?,
+            bank: self.bank,
+            amount: self.amount,
+            accname: self.accname,
+            mobileno: self.mobileno,
+            transaction_by: self.transaction_by,
+            ref1: self.ref1,
+            ref2: self.ref2,
+            ref3: self.ref3,
+            ref4: self.ref4,
+            line_token: self.line_token,
+            email: self.email,
+            allow_duplicate: self.allow_duplicate,
+        })
+    }
+}
+
+impl ConnectionArgs {
+    fn client(&self) -> Result<Client, Box<dyn std::error::Error>> {
+        let required = |value: &Option<String>, name: &str| {
+            value
+                .clone()
+                .ok_or_else(|| format!("--{name} is required for this command"))
+        };
+        let mut builder = Client::builder(
+            &required(&self.channel, "channel")?,
+            &required(&self.partner_code, "partner-code")?,
+            &required(&self.api_key, "api-key")?,
+        )
+        .base_url(&self.base_url)
+        .retry_policy(RetryPolicy {
+            max_attempts: self.max_attempts,
+            ..RetryPolicy::default()
+        });
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+        if let Some(guard) = self.limits.guard()? {
+            builder = builder.limit_guard(Arc::new(guard));
+        }
+        if let Some(code) = self.not_found_code {
+            builder = builder.not_found_code(ApiError::from_code(code));
+        }
+        if let Some(secs) = self.duplicate_window {
+            builder = builder
+                .duplicate_detector(Arc::new(DuplicateDetector::new(Duration::from_secs(secs))));
+        }
+        Ok(builder.build()?)
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    let cli = Cli::parse();
-    let client = Client::new(&cli.channel, &cli.partner_code, &cli.api_key);
-    match cli.command {
-        Commands::Transfer {
-            bankacc,
-            bank,
-            amount,
-            accname,
-            mobileno,
-            transaction_by,
-            ref1,
-            ref2,
-            ref3,
-            ref4,
-            line_token,
-            email,
+    let Cli {
+        connection,
+        command,
+    } = Cli::parse();
+    match command {
+        Commands::Transfer(args) => {
+            let res = connection.client()?.transfer(args.into_request()?).await?;
+            println!("{}", serde_json::to_string_pretty(&res)?);
+        }
+        Commands::Payout {
+            transfer,
+            deadline,
+            journal,
+            large_amount,
         } => {
-            let res = client
-                .transfer(TransferReq {
-                    bankacc,
-                    bank,
-                    amount,
-                    accname,
-                    mobileno,
-                    transaction_by,
-                    ref1,
-                    ref2,
-                    ref3,
-                    ref4,
-                    line_token,
-                    email,
-                })
+            let poll_policy = RetryPolicy {
+                deadline: Some(Duration::from_secs(deadline)),
+                ..PayoutOrchestrator::default_poll_policy()
+            };
+            let mut orchestrator = PayoutOrchestrator::new(connection.client()?)
+                .poll_policy(poll_policy)
+                .large_amount_policy(large_amount);
+            if let Some(path) = journal {
+                orchestrator = orchestrator.journal(Arc::new(FileJournal::open(path)?));
+                orchestrator.ensure_recovered().await?;
+            }
+            let res = orchestrator.payout(transfer.into_request()?).await?;
+            println!("{}", serde_json::to_string_pretty(&res)?);
+        }
+        Commands::Batch {
+            input,
+            results,
+            concurrency,
+            deadline,
+            journal,
+            large_amount,
+        } => {
+            let rows = match BatchRunner::load(&input) {
+                Err(BatchError::Invalid(invalid)) => {
+                    println!("{}", serde_json::to_string_pretty(&invalid)?);
+                    return Err(format!("{} rows are invalid", invalid.len()).into());
+                }
+                rows => rows?,
+            };
+            let poll_policy = RetryPolicy {
+                deadline: Some(Duration::from_secs(deadline)),
+                ..PayoutOrchestrator::default_poll_policy()
+            };
+            let mut orchestrator = PayoutOrchestrator::new(connection.client()?)
+                .poll_policy(poll_policy)
+                .large_amount_policy(large_amount);
+            if let Some(path) = journal {
+                orchestrator = orchestrator.journal(Arc::new(FileJournal::open(path)?));
+                orchestrator.ensure_recovered().await?;
+            }
+            let summary = BatchRunner::new(orchestrator)
+                .concurrency(concurrency)
+                .run(rows, &results)
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            if !summary.is_complete() {
+                return Err(format!("Some rows are not settled, see {}", results.display()).into());
+            }
+        }
+        Commands::Recover { journal } => {
+            let res = PayoutOrchestrator::new(connection.client()?)
+                .journal(Arc::new(FileJournal::open(journal)?))
+                .recover()
                 .await?;
-            println!("{:?}", res);
+            println!("{}", serde_json::to_string_pretty(&res)?);
+        }
+        Commands::Reconcile { input, concurrency } => {
+            let entries = match Reconciler::load(&input) {
+                Err(ReconcileError::Invalid(invalid)) => {
+                    println!("{}", serde_json::to_string_pretty(&invalid)?);
+                    return Err(format!("{} rows are invalid", invalid.len()).into());
+                }
+                entries => entries?,
+            };
+            let report = Reconciler::new(connection.client()?)
+                .concurrency(concurrency)
+                .reconcile(entries)
+                .await;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.is_clean() {
+                return Err(format!(
+                    "{} of {} entries don't match",
+                    report.checked - report.matched,
+                    report.checked
+                )
+                .into());
+            }
         }
         Commands::Inquery { ref1 } => {
-            let res = client.query(QueryReq { ref1 }).await?;
-            println!("{:?}", res);
+            let res = connection.client()?.query(QueryReq { ref1 }).await?;
+            println!("{}", serde_json::to_string_pretty(&res)?);
+        }
+        Commands::Ref1 { prefix, decode } => match decode {
+            Some(ref1) => {
+                let time = Ref1Generator::timestamp(&ref1)
+                    .ok_or_else(|| format!("{ref1} is not a generated ref1"))?;
+                println!("{}", time.to_rfc3339());
+            }
+            None => {
+                let gen = Ref1Generator::with_prefix(prefix.as_deref().unwrap_or_default())?;
+                println!("{}", gen.generate());
+            }
+        },
+        Commands::Slip { qrstring } => {
+            println!("{}", serde_json::to_string_pretty(&qrstring)?);
+        }
+        Commands::Schema { schema_type } => {
+            println!("{}", serde_json::to_string_pretty(&schema_type.schema())?);
         }
     }
     Ok(())
@@ -0,0 +1,25 @@
+#!/usr/bin/env bash
+# Runs transfer and inquery flows against local mock server instead of 1-2-Pay
+set -euo pipefail
+
+# The mock and the client have to agree on the status of unknown ref1
+export NOT_FOUND_CODE=-1010
+cargo build -q -p one-two-pay-mock
+cargo run -q -p one-two-pay-mock -- --listen 127.0.0.1:8080 &
+MOCK_PID=$!
+trap "kill $MOCK_PID" EXIT
+
+# Wait until the mock accepts connections
+for _ in $(seq 50); do
+    if (exec 3<>/dev/tcp/127.0.0.1/8080) 2>/dev/null; then
+        break
+    fi
+    sleep 0.2
+done
+(exec 3<>/dev/tcp/127.0.0.1/8080) 2>/dev/null || { echo "Mock server didn't start" >&2; exit 1; }
+
+export BASE_URL="http://127.0.0.1:8080"
+export REF1=$(cargo run -q -- ref1 --prefix test-)
+export API_KEY="mock-key" PARTNER_CODE="CRS" BANK_ACC="0652078409" BANK_NAME="Manop Tangngam" MOBILE_NUM="0805933181"
+./test_transfer.sh
+./test_inquery.sh
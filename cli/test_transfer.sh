@@ -8,5 +8,5 @@ RUST_LOG=trace cargo run -- \
     --accname "$BANK_NAME" \
     --mobileno $MOBILE_NUM \
     --transaction-by "Test developer" \
-    --ref1 "6957f82b34aa4e1dab67466fe74804" \
+    --ref1 "$REF1" \
     --amount=10
@@ -159,4 +159,35 @@ impl Bank {
             _ => None,
         }
     }
+
+    /// Allowed number of digits in account numbers of the bank. The large
+    /// commercial banks print them as XXX-X-XXXXX-X, 10 digits, like the
+    /// Kasikorn account in the 1-2-Pay docs. Lengths of the other banks are not
+    /// documented, any of 10 to 15 digits is accepted for them.
+    pub fn account_lengths(self) -> &'static [usize] {
+        match self {
+            Bank::Bangkok
+            | Bank::Kasikorn
+            | Bank::KrungThai
+            | Bank::TmbThanachart
+            | Bank::SiamCommercial
+            | Bank::Ayudhya => &[10],
+            _ => &[10, 11, 12, 13, 14, 15],
+        }
+    }
+
+    /// Reads a bank as written in files: code like "004", acronym like "KBANK"
+    /// or name like "Kasikorn" or "kasikorn", case is ignored
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if let Ok(code) = s.parse::<u32>() {
+            return Self::from_code(code);
+        }
+        Self::from_acronym(s).or_else(|| {
+            Self::value_variants().iter().copied().find(|bank| {
+                format!("{bank:?}").eq_ignore_ascii_case(s)
+                    || bank.to_possible_value().map_or(false, |v| v.matches(s, true))
+            })
+        })
+    }
 }
@@ -0,0 +1,503 @@
+//! Payouts to many recipients read from a CSV or JSON lines file, see
+//! [`crate::records`]. Every row is validated before anything is sent. The
+//! result of each row is appended to a results file as soon as it is known, so
+//! an interrupted batch can be run again. It skips rows that already settled
+//! and queries the ones with unclear outcome instead of sending them again. Rows
+//! the provider rejected stay failed, they need a new `ref1`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
+use log::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::journal::open_append;
+use crate::records::{self, RecordsError};
+use crate::{de, PayoutOrchestrator, PayoutOutcome, Ref1, TransferReq, ValidationReport};
+
+/// One row of the input file. Columns have the names of [`TransferReq`]
+/// fields, bank may be a code, an acronym or a name, see [`crate::Bank::parse`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct BatchRow {
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub bankacc: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub bank: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub accname: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub amount: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub mobileno: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub transaction_by: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub ref1: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub ref2: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub ref3: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub ref4: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub line_token: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub email: Option<String>,
+    /// Yes for a legitimate repeat, see [`TransferReq::allow_duplicate`]
+    #[serde(default, deserialize_with = "de::flag")]
+    pub allow_duplicate: bool,
+}
+
+impl BatchRow {
+    pub fn into_request(self) -> Result<TransferReq, ValidationReport> {
+        let mut builder = TransferReq::builder();
+        macro_rules! set {
+            ($($field:ident => $setter:ident),*) => {
+                $(if let Some(value) = &self.$field {
+                    builder = builder.$setter(value);
+                })*
+            };
+        }
+        set!(
+            bankacc => bankacc,
+            bank => bank_str,
+            accname => accname,
+            amount => amount_str,
+            mobileno => mobileno,
+            transaction_by => transaction_by,
+            ref1 => ref1,
+            ref2 => ref2,
+            ref3 => ref3,
+            ref4 => ref4,
+            line_token => line_token,
+            email => email
+        );
+        builder.allow_duplicate(self.allow_duplicate).build()
+    }
+}
+
+/// Row of the input file that didn't pass validation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct InvalidRow {
+    pub line: usize,
+    pub report: ValidationReport,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum BatchError {
+    #[error(transparent)]
+    Records(#[from] RecordsError),
+    #[error("{}", invalid_rows(.0))]
+    Invalid(Vec<InvalidRow>),
+    #[error("ref1 {ref1} is used on lines {first} and {second}")]
+    DuplicateRef1 {
+        ref1: Ref1,
+        first: usize,
+        second: usize,
+    },
+    #[error("Results file IO error: {0}")]
+    Io(Arc<std::io::Error>),
+    #[error("Results file line {line} is corrupted: {error}")]
+    Corrupted {
+        line: usize,
+        error: Arc<serde_json::Error>,
+    },
+}
+
+pub(crate) fn invalid_rows(rows: &[InvalidRow]) -> String {
+    rows.iter()
+        .map(|row| format!("Line {}: {}", row.line, row.report))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// One line of the results file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BatchResult {
+    /// Line of the row in the input file
+    pub line: usize,
+    pub ref1: Ref1,
+    pub at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<PayoutOutcome>,
+    /// The transfer was certainly not sent, see [`PayoutOrchestrator::payout`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Counts of rows by what became of them in one run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct BatchSummary {
+    pub total: usize,
+    /// Settled in an earlier run
+    pub skipped: usize,
+    pub settled: usize,
+    pub failed: usize,
+    pub needs_manual_review: usize,
+    pub unknown: usize,
+    pub errors: usize,
+}
+
+impl BatchSummary {
+    /// Every row is settled
+    pub fn is_complete(&self) -> bool {
+        self.skipped + self.settled == self.total
+    }
+
+    fn count(&mut self, res: &Result<PayoutOutcome, crate::Error>) {
+        match res {
+            Ok(outcome) if outcome.is_settled() => self.settled += 1,
+            Ok(PayoutOutcome::Failed { .. } | PayoutOutcome::Rejected { .. }) => self.failed += 1,
+            Ok(PayoutOutcome::Unknown { .. }) => self.unknown += 1,
+            // Including a split payout that didn't settle in full
+            Ok(_) => self.needs_manual_review += 1,
+            Err(_) => self.errors += 1,
+        }
+    }
+}
+
+/// Runs payouts of a batch through [`PayoutOrchestrator`], a few at a time.
+/// Rows that are not settled are continued on the next run with the same
+/// `ref1`, see [`PayoutOrchestrator::resume`].
+#[derive(Debug, Clone)]
+pub struct BatchRunner {
+    orchestrator: PayoutOrchestrator,
+    concurrency: usize,
+}
+
+impl BatchRunner {
+    pub fn new(orchestrator: PayoutOrchestrator) -> Self {
+        BatchRunner {
+            orchestrator,
+            concurrency: 4,
+        }
+    }
+
+    /// How many payouts run at the same time, at least 1
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Reads and validates every row of the file. Fails with all invalid rows
+    /// or if two rows have the same `ref1`.
+    pub fn load(path: &Path) -> Result<Vec<(usize, TransferReq)>, BatchError> {
+        let rows: Vec<(usize, BatchRow)> = records::read_file(path)?;
+        let mut requests = Vec::with_capacity(rows.len());
+        let mut invalid = vec![];
+        for (line, row) in rows {
+            match row.into_request() {
+                Ok(req) => requests.push((line, req)),
+                Err(report) => invalid.push(InvalidRow { line, report }),
+            }
+        }
+        if !invalid.is_empty() {
+            return Err(BatchError::Invalid(invalid));
+        }
+        let mut lines: HashMap<&Ref1, usize> = HashMap::new();
+        for (line, req) in requests.iter() {
+            if let Some(first) = lines.insert(&req.ref1, *line) {
+                return Err(BatchError::DuplicateRef1 {
+                    ref1: req.ref1.clone(),
+                    first,
+                    second: *line,
+                });
+            }
+        }
+        Ok(requests)
+    }
+
+    /// Reads a results file, missing file has no results. A line cut short by a
+    /// crash is skipped.
+    pub fn results(path: &Path) -> Result<Vec<BatchResult>, BatchError> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(BatchError::Io(Arc::new(e))),
+        };
+        let mut results = vec![];
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| BatchError::Io(Arc::new(e)))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(result) => results.push(result),
+                Err(e) if e.is_eof() => warn!("Skipping incomplete results line {}: {e}", i + 1),
+                Err(e) => {
+                    return Err(BatchError::Corrupted {
+                        line: i + 1,
+                        error: Arc::new(e),
+                    })
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Pays every row that has no settled result in the results file yet and
+    /// appends the results. The file keeps the results of earlier runs. A row
+    /// that has an outcome there is resumed from it rather than paid anew.
+    pub async fn run(
+        &self,
+        rows: Vec<(usize, TransferReq)>,
+        results: &Path,
+    ) -> Result<BatchSummary, BatchError> {
+        // The last result of a row counts, a row that ended with an error was
+        // certainly not sent
+        let mut previous: HashMap<Ref1, PayoutOutcome> = HashMap::new();
+        for result in Self::results(results)? {
+            match result.outcome {
+                Some(outcome) => previous.insert(result.ref1, outcome),
+                None => previous.remove(&result.ref1),
+            };
+        }
+        let mut file = open_append(results).map_err(|e| BatchError::Io(Arc::new(e)))?;
+        let mut summary = BatchSummary {
+            total: rows.len(),
+            ..BatchSummary::default()
+        };
+        let pending: Vec<_> = rows
+            .into_iter()
+            .filter_map(|(line, req)| {
+                let previous = previous.remove(&req.ref1);
+                if previous.as_ref().map_or(false, PayoutOutcome::is_settled) {
+                    info!("Skipping line {line}, {} is settled", req.ref1);
+                    return None;
+                }
+                Some((line, req, previous))
+            })
+            .collect();
+        summary.skipped = summary.total - pending.len();
+
+        let mut payouts = stream::iter(pending)
+            .map(|(line, req, previous)| async move {
+                let ref1 = req.ref1.clone();
+                let res = match previous {
+                    Some(previous) => self.orchestrator.resume(req, previous).await,
+                    None => self.orchestrator.payout(req).await,
+                };
+                (line, ref1, res)
+            })
+            .buffer_unordered(self.concurrency);
+        // Payouts in progress are not dropped on a write error, the money may be
+        // moving already
+        let mut write_error = None;
+        while let Some((line, ref1, res)) = payouts.next().await {
+            summary.count(&res);
+            let result = BatchResult {
+                line,
+                ref1,
+                at: Utc::now(),
+                error: res.as_ref().err().map(ToString::to_string),
+                outcome: res.ok(),
+            };
+            if let Err(e) = write_result(&mut file, &result) {
+                error!("Failed to write result of line {line}: {e}");
+                write_error.get_or_insert(e);
+            }
+        }
+        match write_error {
+            Some(e) => Err(BatchError::Io(Arc::new(e))),
+            None => Ok(summary),
+        }
+    }
+}
+
+fn write_result(file: &mut File, result: &BatchResult) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(result)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    file.sync_data()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+    use crate::{Client, RetryPolicy, PAYOUT_PATH, QUERY_PATH};
+    use serde_json::json;
+    use std::time::Duration;
+
+    const CSV: &str = "bankacc,bank,accname,amount,mobileno,transaction_by,ref1
+0652078409,KBANK,Manop Tangngam,\"1,000.50\",0805933181,Jack Developer,batch-1
+0652078409,004,Somchai Jaidee,20,0805933181,Jack Developer,batch-2
+";
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("one-two-pay-{}-{name}", Ref1::generate()))
+    }
+
+    fn runner(mock: Arc<MockTransport>) -> BatchRunner {
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock)
+            .build()
+            .expect("client");
+        let orchestrator = PayoutOrchestrator::new(client).poll_policy(RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            multiplier: 1.0,
+            jitter: 0.0,
+            deadline: None,
+        });
+        BatchRunner::new(orchestrator).concurrency(1)
+    }
+
+    fn transfer_success() -> serde_json::Value {
+        json!({
+            "status": 1000,
+            "message": "Success",
+            "payout_ref": "2022051790WiXyi9Lwu0iuHgT",
+            "transaction_id": "2022051790WiXyi9Lwu0iuHgT",
+            "transactionDate_time": "2022-05-17 08:41:50.447",
+            "qrstring": "0041000600000101030040220"
+        })
+    }
+
+    #[test]
+    fn load_validates_all_rows() {
+        let input = temp_path("input.csv");
+        std::fs::write(&input, CSV).expect("written");
+        let rows = BatchRunner::load(&input).expect("loaded");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].0, 3);
+        assert_eq!(rows[1].1.bank, crate::Bank::Kasikorn);
+
+        std::fs::write(
+            &input,
+            format!("{CSV}0652078409,XYZ,,1,0805933181,Jack Developer,batch-3\n"),
+        )
+        .expect("written");
+        let Err(BatchError::Invalid(invalid)) = BatchRunner::load(&input) else {
+            panic!("rows are invalid");
+        };
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].line, 4);
+        assert_eq!(invalid[0].report.issues.len(), 2);
+
+        std::fs::write(&input, format!("{CSV}{}", CSV.lines().nth(1).expect("row")))
+            .expect("written");
+        assert!(matches!(
+            BatchRunner::load(&input),
+            Err(BatchError::DuplicateRef1 {
+                first: 2,
+                second: 4,
+                ..
+            })
+        ));
+        std::fs::remove_file(&input).expect("removed");
+    }
+
+    #[tokio::test]
+    async fn resumes_after_interruption() {
+        let input = temp_path("input.jsonl");
+        let results = temp_path("results.jsonl");
+        std::fs::write(
+            &input,
+            "{\"bankacc\": \"0652078409\", \"bank\": \"Kasikorn\", \"accname\": \"Manop\", \"amount\": 1.5, \"mobileno\": \"0805933181\", \"transaction_by\": \"Jack\", \"ref1\": \"batch-1\"}\n\
+             {\"bankacc\": \"0652078409\", \"bank\": \"SCB\", \"accname\": \"Somchai\", \"amount\": \"20\", \"mobileno\": \"0805933181\", \"transaction_by\": \"Jack\", \"ref1\": \"batch-2\"}\n",
+        )
+        .expect("written");
+        let rows = BatchRunner::load(&input).expect("loaded");
+
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(transfer_success());
+        // Nothing scripted for the second row, so it is not delivered
+        let summary = runner(mock.clone())
+            .run(rows.clone(), &results)
+            .await
+            .expect("run");
+        assert_eq!((summary.settled, summary.errors), (1, 1));
+        assert!(!summary.is_complete());
+
+        // Crash in the middle of writing a result
+        let mut file = open_append(&results).expect("opened");
+        file.write_all(b"{\"line\":2,").expect("written");
+
+        mock.push_transfer(transfer_success());
+        let summary = runner(mock.clone()).run(rows, &results).await.expect("run");
+        assert_eq!((summary.skipped, summary.settled), (1, 1));
+        assert!(summary.is_complete());
+        let sent: Vec<String> = mock
+            .requests()
+            .into_iter()
+            .filter(|r| r.path == PAYOUT_PATH)
+            .map(|r| {
+                serde_json::from_str::<serde_json::Value>(&r.body).expect("json")["ref1"]
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(sent, vec!["\"batch-1\"", "\"batch-2\"", "\"batch-2\""]);
+
+        let written = BatchRunner::results(&results).expect("results");
+        assert_eq!(written.len(), 3);
+        assert!(written[1].outcome.is_none() && written[1].error.is_some());
+        std::fs::remove_file(&input).expect("removed");
+        std::fs::remove_file(&results).expect("removed");
+    }
+
+    #[tokio::test]
+    async fn unclear_rows_are_queried_on_resume() {
+        let input = temp_path("input.csv");
+        let results = temp_path("results.jsonl");
+        std::fs::write(&input, CSV).expect("written");
+        let rows = BatchRunner::load(&input).expect("loaded");
+
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 9090, "message": "Waiting"}));
+        mock.push_transfer(json!({"status": -2000, "message": "Manual transfer"}));
+        let summary = runner(mock.clone())
+            .run(rows.clone(), &results)
+            .await
+            .expect("run");
+        assert_eq!((summary.unknown, summary.needs_manual_review), (1, 1));
+
+        mock.push_query(json!({
+            "status": "1000",
+            "message": "Success",
+            "accname": "MANOP TANGNGAM",
+            "bankacc": "0652078409",
+            "bankcode": "004",
+            "amount": "1,000.50",
+            "ref1": "batch-1",
+            "created_date": "2022-05-17 08:41:48.320",
+            "transfer_date": "2022-05-17 08:41:50.447",
+            "transfer_transactionId": "2022051790WiXyi9Lwu0iuHgT"
+        }));
+        mock.push_query(json!({"status": "-2000", "message": "Manual transfer"}));
+        let summary = runner(mock.clone())
+            .run(rows.clone(), &results)
+            .await
+            .expect("run");
+        assert_eq!((summary.settled, summary.needs_manual_review), (1, 1));
+        let paths: Vec<String> = mock.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![PAYOUT_PATH, PAYOUT_PATH, QUERY_PATH, QUERY_PATH]
+        );
+
+        // A rejected row keeps its outcome, the provider won't take its ref1 again
+        std::fs::remove_file(&results).expect("removed");
+        mock.push_transfer(transfer_success());
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        let summary = runner(mock.clone())
+            .run(rows.clone(), &results)
+            .await
+            .expect("run");
+        assert_eq!((summary.settled, summary.failed), (1, 1));
+        let sent = mock.requests().len();
+        let summary = runner(mock.clone()).run(rows, &results).await.expect("run");
+        assert_eq!((summary.skipped, summary.failed), (1, 1));
+        assert_eq!(mock.requests().len(), sent);
+        std::fs::remove_file(&input).expect("removed");
+        std::fs::remove_file(&results).expect("removed");
+    }
+}
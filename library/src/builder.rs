@@ -0,0 +1,225 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Proxy};
+
+use crate::dedup::DuplicateDetector;
+use crate::error::{ApiError, Error};
+use crate::guard::LimitGuard;
+use crate::retry::RetryPolicy;
+use crate::secret::Secret;
+use crate::transport::{ReqwestTransport, Transport, TransportError};
+use crate::{Client, ONE_TWO_PAY_URL};
+
+/// Configures a [`Client`] before it is created. Use it to point the client at a
+/// sandbox or a local stand-in and to tune the underlying HTTP pool.
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    base_url: String,
+    channel: String,
+    partnercode: String,
+    api_key: Secret,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+    proxy: Option<Proxy>,
+    root_certificates: Vec<Certificate>,
+    default_headers: HeaderMap,
+    transport: Option<Arc<dyn Transport>>,
+    retry_policy: RetryPolicy,
+    limit_guard: Option<Arc<LimitGuard>>,
+    duplicate_detector: Option<Arc<DuplicateDetector>>,
+    not_found_code: Option<ApiError>,
+}
+
+impl ClientBuilder {
+    pub fn new(channel: &str, partner_code: &str, api_key: &str) -> Self {
+        ClientBuilder {
+            base_url: ONE_TWO_PAY_URL.to_owned(),
+            channel: channel.to_owned(),
+            partnercode: partner_code.to_owned(),
+            api_key: Secret::new(api_key),
+            connect_timeout: None,
+            timeout: None,
+            user_agent: None,
+            proxy: None,
+            root_certificates: vec![],
+            default_headers: HeaderMap::new(),
+            transport: None,
+            retry_policy: RetryPolicy::none(),
+            limit_guard: None,
+            duplicate_detector: None,
+            not_found_code: None,
+        }
+    }
+
+    /// Base URL of the API without trailing slash. Default: [`ONE_TWO_PAY_URL`]
+    pub fn base_url(mut self, url: &str) -> Self {
+        self.base_url = url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Timeout for establishing TCP/TLS connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for the whole request, from connecting until the body is read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_owned());
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Trust additional root certificate, e.g. of a corporate TLS proxy
+    pub fn add_root_certificate(mut self, cert: Certificate) -> Self {
+        self.root_certificates.push(cert);
+        self
+    }
+
+    /// Headers that are sent with every request in addition to the auth ones
+    pub fn default_headers(mut self, headers: HeaderMap) -> Self {
+        self.default_headers = headers;
+        self
+    }
+
+    /// Send requests via custom transport. HTTP settings above are ignored then.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Retry network failures and temporary API errors. Default: no retries
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Check transfers against client side limits before sending them
+    pub fn limit_guard(mut self, guard: Arc<LimitGuard>) -> Self {
+        self.limit_guard = Some(guard);
+        self
+    }
+
+    /// Refuse transfers that repeat a recent one before sending them
+    pub fn duplicate_detector(mut self, detector: Arc<DuplicateDetector>) -> Self {
+        self.duplicate_detector = Some(detector);
+        self
+    }
+
+    /// Status the provider answers when a queried `ref1` doesn't exist. It is not
+    /// documented, so without it such answers are errors and a transfer that
+    /// may have been sent is never sent again. Default: none
+    pub fn not_found_code(mut self, code: ApiError) -> Self {
+        self.not_found_code = Some(code);
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => {
+                let mut builder = reqwest::Client::builder().default_headers(self.default_headers);
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(user_agent) = self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                if let Some(proxy) = self.proxy {
+                    builder = builder.proxy(proxy);
+                }
+                for cert in self.root_certificates {
+                    builder = builder.add_root_certificate(cert);
+                }
+                let http = builder
+                    .build()
+                    .map_err(|e| Error::Transport(TransportError::Reqwest(Arc::new(e))))?;
+                Arc::new(ReqwestTransport::new(&self.base_url, http))
+            }
+        };
+        let mut client = Client::with_transport(
+            &self.channel,
+            &self.partnercode,
+            self.api_key.expose(),
+            transport,
+        )
+        .with_retry_policy(self.retry_policy);
+        if let Some(guard) = self.limit_guard {
+            client = client.with_limit_guard(guard);
+        }
+        if let Some(detector) = self.duplicate_detector {
+            client = client.with_duplicate_detector(detector);
+        }
+        if let Some(code) = self.not_found_code {
+            client = client.with_not_found_code(code);
+        }
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+    use crate::{QueryReq, QUERY_PATH};
+    use serde_json::json;
+
+    #[test]
+    fn base_url_without_trailing_slash() {
+        let builder = ClientBuilder::new("WEB", "CRS", "secret");
+        assert_eq!(builder.base_url, ONE_TWO_PAY_URL);
+        let builder = builder.base_url("http://127.0.0.1:8080//");
+        assert_eq!(builder.base_url, "http://127.0.0.1:8080");
+        assert_eq!(
+            ReqwestTransport::new("http://127.0.0.1:8080/", reqwest::Client::new()).base_url(),
+            "http://127.0.0.1:8080"
+        );
+    }
+
+    #[test]
+    fn timeouts() {
+        let builder = Client::builder("WEB", "CRS", "secret")
+            .connect_timeout(Duration::from_secs(3))
+            .timeout(Duration::from_secs(20));
+        assert_eq!(builder.connect_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(builder.timeout, Some(Duration::from_secs(20)));
+        assert!(builder.build().is_ok());
+    }
+
+    #[tokio::test]
+    async fn transport_override() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_query(json!({"status": "9001", "message": "Temporarily unavailable"}));
+        let client = Client::builder("WEB", "CRS", "secret")
+            .base_url("http://127.0.0.1:1")
+            .timeout(Duration::from_millis(1))
+            .transport(mock.clone())
+            .build()
+            .expect("client");
+        let err = client
+            .query(QueryReq {
+                ref1: "202205170841".to_owned(),
+            })
+            .await
+            .expect_err("scripted failure");
+        assert_eq!(err.api_code(), Some(ApiError::ServiceUnavailable));
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, QUERY_PATH);
+    }
+}
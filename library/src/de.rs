@@ -0,0 +1,112 @@
+//! Deserializers for fields the provider sends either as string or as number
+
+use serde::{de, Deserialize, Deserializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    Str(String),
+    Int(i64),
+    Float(f64),
+}
+
+/// Status code like `1000` or `"1000"`
+pub(crate) fn status<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::Str(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| de::Error::custom(format!("status is not integer: {s}"))),
+        StringOrNumber::Int(i) => {
+            i32::try_from(i).map_err(|_| de::Error::custom(format!("status is out of range: {i}")))
+        }
+        StringOrNumber::Float(f) => Err(de::Error::custom(format!("status is not integer: {f}"))),
+    }
+}
+
+/// Optional text field that may come as number, e.g. amount `1.5` or `"1.50"`
+pub(crate) fn opt_string<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(
+        Option::<StringOrNumber>::deserialize(deserializer)?.map(|v| match v {
+            StringOrNumber::Str(s) => s,
+            StringOrNumber::Int(i) => i.to_string(),
+            StringOrNumber::Float(f) => f.to_string(),
+        }),
+    )
+}
+
+/// Flag like `true`, `"yes"`, `1` or empty, for files written by hand
+pub(crate) fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Other(StringOrNumber),
+    }
+    let text = match Option::<Flag>::deserialize(deserializer)? {
+        None => return Ok(false),
+        Some(Flag::Bool(b)) => return Ok(b),
+        Some(Flag::Other(StringOrNumber::Str(s))) => s,
+        Some(Flag::Other(StringOrNumber::Int(i))) => i.to_string(),
+        Some(Flag::Other(StringOrNumber::Float(f))) => f.to_string(),
+    };
+    match text.trim().to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" => Ok(true),
+        "false" | "no" | "n" | "0" | "" => Ok(false),
+        _ => Err(de::Error::custom(format!("not a yes/no value: {text}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        #[serde(deserialize_with = "status")]
+        status: i32,
+        #[serde(default, deserialize_with = "opt_string")]
+        amount: Option<String>,
+    }
+
+    fn parse(s: &str) -> Result<(i32, Option<String>), serde_json::Error> {
+        serde_json::from_str::<Body>(s).map(|b| (b.status, b.amount))
+    }
+
+    #[test]
+    fn string_or_number() {
+        assert_eq!(parse(r#"{"status": 5009}"#).ok(), Some((5009, None)));
+        assert_eq!(
+            parse(r#"{"status": "-1003", "amount": 1.5}"#).ok(),
+            Some((-1003, Some("1.5".to_owned())))
+        );
+        assert_eq!(
+            parse(r#"{"status": "1000", "amount": "1,000.00"}"#).ok(),
+            Some((1000, Some("1,000.00".to_owned())))
+        );
+        assert_eq!(
+            parse(r#"{"status": 1000, "amount": null}"#).ok(),
+            Some((1000, None))
+        );
+        assert!(parse(r#"{"status": "ok"}"#).is_err());
+        assert!(parse(r#"{"status": 10.5}"#).is_err());
+    }
+
+    #[test]
+    fn flags() {
+        #[derive(Deserialize)]
+        struct Row {
+            #[serde(default, deserialize_with = "flag")]
+            flag: bool,
+        }
+        let parse = |s: &str| serde_json::from_str::<Row>(s).map(|r| r.flag).ok();
+        assert_eq!(parse(r#"{"flag": true}"#), Some(true));
+        assert_eq!(parse(r#"{"flag": " Yes"}"#), Some(true));
+        assert_eq!(parse(r#"{"flag": 1}"#), Some(true));
+        assert_eq!(parse(r#"{"flag": "no"}"#), Some(false));
+        assert_eq!(parse(r#"{}"#), Some(false));
+        assert_eq!(parse(r#"{"flag": "maybe"}"#), None);
+    }
+}
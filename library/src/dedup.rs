@@ -0,0 +1,183 @@
+//! In-process detection of repeated transfers. The provider rejects a transfer
+//! of the same amount to the same account (9003) only after the round trip,
+//! this catches it before sending. Sending the same `ref1` again, e.g. on retry,
+//! is not a duplicate.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{Bank, BankAccount, Ref1, Thb, TransferReq};
+
+/// Transfer that repeats a recent one with another `ref1`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Error)]
+#[error("{amount} THB to {bankacc} of {} was already sent at {sent_at} as {ref1}, allow duplicate to send it again", bank.to_acronym())]
+pub struct DuplicateSuspected {
+    pub bank: Bank,
+    pub bankacc: BankAccount,
+    pub amount: Thb,
+    /// `ref1` of the earlier transfer
+    pub ref1: Ref1,
+    pub sent_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Recent {
+    seen: Instant,
+    transfer: DuplicateSuspected,
+}
+
+/// Remembers (bank, account, amount) of transfers for a while, see
+/// [`crate::ClientBuilder::duplicate_detector`]
+#[derive(Debug)]
+pub struct DuplicateDetector {
+    window: Duration,
+    recent: Mutex<Vec<Recent>>,
+}
+
+impl DuplicateDetector {
+    pub fn new(window: Duration) -> Self {
+        DuplicateDetector {
+            window,
+            recent: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Fails if another `ref1` with the same bank, account and amount was seen
+    /// within the window, unless the request has
+    /// [`TransferReq::allow_duplicate`]. Otherwise the transfer is remembered.
+    /// Returns false if its `ref1` was remembered already, then only the earlier
+    /// call may [`DuplicateDetector::forget`] it.
+    pub fn check(&self, req: &TransferReq) -> Result<bool, DuplicateSuspected> {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().expect("duplicate detector lock");
+        recent.retain(|r| now.duration_since(r.seen) < self.window);
+        let same = |r: &&Recent| {
+            r.transfer.bank == req.bank
+                && r.transfer.bankacc == req.bankacc
+                && r.transfer.amount == req.amount
+        };
+        if let Some(earlier) = recent
+            .iter()
+            .filter(same)
+            .find(|r| r.transfer.ref1 != req.ref1)
+        {
+            if !req.allow_duplicate {
+                return Err(earlier.transfer.clone());
+            }
+        }
+        if recent.iter().any(|r| r.transfer.ref1 == req.ref1) {
+            return Ok(false);
+        }
+        recent.push(Recent {
+            seen: now,
+            transfer: DuplicateSuspected {
+                bank: req.bank,
+                bankacc: req.bankacc.clone(),
+                amount: req.amount,
+                ref1: req.ref1.clone(),
+                sent_at: Utc::now(),
+            },
+        });
+        Ok(true)
+    }
+
+    /// Forgets the transfer, e.g. it certainly wasn't paid
+    pub fn forget(&self, ref1: &Ref1) {
+        self.recent
+            .lock()
+            .expect("duplicate detector lock")
+            .retain(|r| r.transfer.ref1 != *ref1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::transport::MockTransport;
+    use crate::Client;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn request(ref1: &str, amount: &str) -> TransferReq {
+        TransferReq::builder()
+            .bankacc("0652078409")
+            .bank(Bank::Kasikorn)
+            .accname("Manop Tangngam")
+            .amount_str(amount)
+            .mobileno("0805933181")
+            .transaction_by("Jack Developer")
+            .ref1(ref1)
+            .build()
+            .expect("request")
+    }
+
+    #[test]
+    fn repeats_within_window() {
+        let detector = DuplicateDetector::new(Duration::from_secs(60));
+        assert!(detector.check(&request("a-1", "10")).expect("first"));
+        assert!(!detector.check(&request("a-1", "10")).expect("same ref1"));
+        detector.check(&request("a-2", "11")).expect("other amount");
+        let suspected = detector.check(&request("a-3", "10")).expect_err("repeat");
+        assert_eq!(suspected.ref1.as_str(), "a-1");
+        let mut allowed = request("a-3", "10");
+        allowed.allow_duplicate = true;
+        detector.check(&allowed).expect("allowed");
+
+        detector.forget(&"a-2".parse().expect("ref1"));
+        detector.check(&request("a-4", "11")).expect("forgotten");
+
+        let detector = DuplicateDetector::new(Duration::ZERO);
+        detector.check(&request("a-1", "10")).expect("first");
+        detector
+            .check(&request("a-2", "10"))
+            .expect("window passed");
+    }
+
+    #[tokio::test]
+    async fn client_blocks_duplicates() {
+        let mock = Arc::new(MockTransport::new());
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock.clone())
+            .duplicate_detector(Arc::new(DuplicateDetector::new(Duration::from_secs(60))))
+            .build()
+            .expect("client");
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        client
+            .transfer(request("a-1", "10"))
+            .await
+            .expect_err("not paid");
+        mock.push_transfer(json!({"status": 9090, "message": "Waiting"}));
+        client
+            .transfer(request("a-2", "10"))
+            .await
+            .expect_err("pending");
+        // Unpaid repeat of a remembered ref1 doesn't forget it
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        client
+            .transfer(request("a-2", "10"))
+            .await
+            .expect_err("not paid");
+        let res = client.transfer(request("a-3", "10")).await;
+        assert!(matches!(res, Err(Error::DuplicateSuspected(_))), "{res:?}");
+
+        // The bank may still pay a transfer it didn't answer
+        mock.push_transfer(json!({"status": 9091, "message": "no response"}));
+        client
+            .transfer(request("b-1", "20"))
+            .await
+            .expect_err("no response");
+        let res = client.transfer(request("b-2", "20")).await;
+        assert!(matches!(res, Err(Error::DuplicateSuspected(_))), "{res:?}");
+        assert_eq!(mock.requests().len(), 4);
+    }
+}
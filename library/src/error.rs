@@ -1,45 +1,408 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 use thiserror::Error;
 
-use crate::{query::QueryResError, transfer::TransferConvError};
+use crate::{
+    dedup::DuplicateSuspected,
+    fields::{FieldError, Ref1},
+    guard::{GuardError, LimitBreach},
+    journal::JournalError,
+    money::Thb,
+    query::QueryResError,
+    secret::masked_body,
+    transfer::TransferConvError,
+    transport::TransportError,
+};
 
 #[derive(Debug, Clone, Error)]
 pub enum Error {
-    #[error("API error: {0}")]
-    Api(ApiError),
+    #[error("API error: {0}. {1}")]
+    Api(ApiError, ResponseInfo),
+    /// Failed to set up the HTTP client, no request was made
     #[error("Network error: {0}")]
-    Reqwest(Arc<reqwest::Error>),
+    Transport(TransportError),
+    /// The request certainly didn't reach the server, it is safe to send it again
+    #[error("Request was not delivered: {0}")]
+    NotDelivered(TransportError),
+    /// The request may have reached the server, but no answer came back. A
+    /// transfer may or may not have been made, query `ref1` to find out.
+    #[error("Request was sent, outcome is unknown: {0}")]
+    OutcomeUnknown(TransportError),
+    #[error("Failed to encode JSON body: {0}")]
+    Json(Arc<serde_json::Error>),
+    #[error("Failed to decode response: {0}. {1}")]
+    Decode(Arc<serde_json::Error>, ResponseInfo),
     #[error("Payout method conversion: {0}")]
     ConvertTransfer(TransferConvError),
     #[error("Query method conversion: {0}")]
     ConvertQuery(QueryResError),
     #[error("Header 'type' failed to encode: {0}")]
     TypeHeaderEncoding(Arc<serde_json::Error>),
-    #[error("ref1 {0} field must have length >= 1 and <= 30")]
-    RefLength(String),
+    #[error("Invalid field: {0}")]
+    Field(FieldError),
+    #[error("Amount must be positive, got {0} THB")]
+    NotPositiveAmount(Thb),
+    #[error("Payout journal: {0}")]
+    Journal(JournalError),
+    /// Rejected by [`crate::guard::LimitGuard`] before it was sent
+    #[error("Transfer limit exceeded: {0}")]
+    LimitExceeded(LimitBreach),
+    #[error("Limit guard: {0}")]
+    Guard(GuardError),
+    /// Rejected by [`crate::dedup::DuplicateDetector`] before it was sent
+    #[error("Duplicate suspected: {0}")]
+    DuplicateSuspected(DuplicateSuspected),
+    /// The journal has payouts that may still be moving, see
+    /// [`crate::PayoutOrchestrator::ensure_recovered`]
+    #[error("Payouts in flight in the journal, resolve them first: {}", join_ref1(.0))]
+    InFlight(Vec<Ref1>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct ApiError(i32);
+fn join_ref1(refs: &[Ref1]) -> String {
+    refs.iter().map(Ref1::as_str).collect::<Vec<_>>().join(", ")
+}
+
+impl Error {
+    /// Status code returned by the API if the error came from it
+    pub fn api_code(&self) -> Option<ApiError> {
+        match self {
+            Error::Api(code, _) => Some(*code),
+            Error::ConvertTransfer(TransferConvError::Api(code)) => Some(*code),
+            Error::ConvertQuery(QueryResError::ApiError(code)) => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// What the server answered, if it answered at all
+    pub fn response(&self) -> Option<&ResponseInfo> {
+        match self {
+            Error::Api(_, info) | Error::Decode(_, info) => Some(info),
+            _ => None,
+        }
+    }
+
+    /// The server may have acted on the request even though we got no usable
+    /// answer, so a transfer has to be checked before it is sent again
+    pub fn is_outcome_unknown(&self) -> bool {
+        matches!(self, Error::OutcomeUnknown(_) | Error::Decode(..))
+    }
+
+    /// Network failures and codes that mean "try again later"
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::NotDelivered(_) | Error::OutcomeUnknown(_) => true,
+            _ => self.api_code().map_or(false, |c| c.is_retryable()),
+        }
+    }
+
+    /// The transfer certainly wasn't paid. Duplicate codes of the API are not
+    /// counted as unpaid, an earlier transfer with the same `ref1` may exist, and
+    /// neither are "try again later" codes, e.g. 9091 may still settle.
+    pub fn is_unpaid(&self) -> bool {
+        match self {
+            Error::NotDelivered(_)
+            | Error::Transport(_)
+            | Error::Json(_)
+            | Error::Guard(_)
+            | Error::Field(_)
+            | Error::NotPositiveAmount(_)
+            | Error::LimitExceeded(_)
+            | Error::DuplicateSuspected(_) => true,
+            e => e.api_code().map_or(false, |code| {
+                matches!(
+                    code.category(),
+                    ApiErrorCategory::InsufficientFunds
+                        | ApiErrorCategory::LimitExceeded
+                        | ApiErrorCategory::InvalidRequest
+                        | ApiErrorCategory::AuthFailure
+                )
+            }),
+        }
+    }
+}
+
+/// Raw answer of the server kept in errors, so unknown codes and gateway failures
+/// can be diagnosed from logs.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ResponseInfo {
+    /// HTTP status code
+    pub status: u16,
+    /// Provider's `message` field if the body was parsed
+    pub message: Option<String>,
+    /// Body as it was received
+    pub body: String,
+}
+
+impl ResponseInfo {
+    /// How much of the body is printed by `Display`, full body is in the field
+    const DISPLAY_BODY_LIMIT: usize = 512;
+}
+
+/// Masks personal data in the body like `Display`
+impl std::fmt::Debug for ResponseInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseInfo")
+            .field("status", &self.status)
+            .field("message", &self.message)
+            .field("body", &masked_body(&self.body))
+            .finish()
+    }
+}
+
+impl Display for ResponseInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP status: {}", self.status)?;
+        if let Some(message) = &self.message {
+            write!(f, ", message: {message}")?;
+        }
+        // Errors end up in logs, so personal data is masked like in `trace!`
+        let body = masked_body(&self.body);
+        match body.char_indices().nth(Self::DISPLAY_BODY_LIMIT) {
+            Some((i, _)) => write!(f, ", body: {}...", &body[..i]),
+            None => write!(f, ", body: {body}"),
+        }
+    }
+}
+
+/// Status codes documented by 1-2-Pay. Codes that are not listed there are kept
+/// in [`ApiError::Unknown`]. Values are compared by code, so `Unknown(1000)`
+/// equals `Success`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(from = "i32", into = "i32")]
+#[non_exhaustive]
+pub enum ApiError {
+    /// 1000
+    Success,
+    /// -2000, amount over 100,000 THB is transferred manually
+    ManualTransfer,
+    /// 1899
+    CannotProcess1899,
+    /// 1999
+    CannotProcess1999,
+    /// 5009
+    IncorrectAccount,
+    /// 5016
+    NotArabicNumerals,
+    /// 6000
+    DailyLimitExceeded,
+    /// 9001
+    ServiceUnavailable,
+    /// 9003
+    SimilarTransfer,
+    /// -1001
+    InvalidJson,
+    /// -1002
+    InvalidAuthorization,
+    /// -1003
+    DuplicateTransaction,
+    /// -1004
+    InvalidPayoutConfig,
+    /// -1009
+    InsufficientBalance,
+    /// 9091
+    BankNoResponse,
+    /// 9090
+    WaitingBankResponse,
+    Unknown(i32),
+}
+
+/// What a status code means for the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum ApiErrorCategory {
+    /// Money is transferred
+    Success,
+    /// Nothing is transferred, the same request can be sent later
+    Retryable,
+    /// The bank hasn't answered yet, query the transaction later
+    Pending,
+    /// Will be transferred manually by the provider
+    ManualReview,
+    /// The same or similar transfer was already made
+    DuplicateSuspected,
+    /// Partner balance is not enough
+    InsufficientFunds,
+    /// Daily transfer limit is reached
+    LimitExceeded,
+    /// Request has invalid fields, resending it won't help
+    InvalidRequest,
+    /// Credentials or partner configuration are wrong
+    AuthFailure,
+    /// Code that is not documented
+    Unknown,
+}
 
 impl ApiError {
     pub fn to_code(self) -> i32 {
-        self.0
+        match self {
+            ApiError::Success => 1000,
+            ApiError::ManualTransfer => -2000,
+            ApiError::CannotProcess1899 => 1899,
+            ApiError::CannotProcess1999 => 1999,
+            ApiError::IncorrectAccount => 5009,
+            ApiError::NotArabicNumerals => 5016,
+            ApiError::DailyLimitExceeded => 6000,
+            ApiError::ServiceUnavailable => 9001,
+            ApiError::SimilarTransfer => 9003,
+            ApiError::InvalidJson => -1001,
+            ApiError::InvalidAuthorization => -1002,
+            ApiError::DuplicateTransaction => -1003,
+            ApiError::InvalidPayoutConfig => -1004,
+            ApiError::InsufficientBalance => -1009,
+            ApiError::BankNoResponse => 9091,
+            ApiError::WaitingBankResponse => 9090,
+            ApiError::Unknown(c) => c,
+        }
     }
 
     pub fn from_code(code: i32) -> Self {
-        ApiError(code)
+        match code {
+            1000 => ApiError::Success,
+            -2000 => ApiError::ManualTransfer,
+            1899 => ApiError::CannotProcess1899,
+            1999 => ApiError::CannotProcess1999,
+            5009 => ApiError::IncorrectAccount,
+            5016 => ApiError::NotArabicNumerals,
+            6000 => ApiError::DailyLimitExceeded,
+            9001 => ApiError::ServiceUnavailable,
+            9003 => ApiError::SimilarTransfer,
+            -1001 => ApiError::InvalidJson,
+            -1002 => ApiError::InvalidAuthorization,
+            -1003 => ApiError::DuplicateTransaction,
+            -1004 => ApiError::InvalidPayoutConfig,
+            -1009 => ApiError::InsufficientBalance,
+            9091 => ApiError::BankNoResponse,
+            9090 => ApiError::WaitingBankResponse,
+            c => ApiError::Unknown(c),
+        }
+    }
+
+    pub fn category(self) -> ApiErrorCategory {
+        match self {
+            ApiError::Success => ApiErrorCategory::Success,
+            ApiError::CannotProcess1899
+            | ApiError::CannotProcess1999
+            | ApiError::ServiceUnavailable
+            | ApiError::BankNoResponse => ApiErrorCategory::Retryable,
+            ApiError::WaitingBankResponse => ApiErrorCategory::Pending,
+            ApiError::ManualTransfer => ApiErrorCategory::ManualReview,
+            ApiError::SimilarTransfer | ApiError::DuplicateTransaction => {
+                ApiErrorCategory::DuplicateSuspected
+            }
+            ApiError::InsufficientBalance => ApiErrorCategory::InsufficientFunds,
+            ApiError::DailyLimitExceeded => ApiErrorCategory::LimitExceeded,
+            ApiError::IncorrectAccount | ApiError::NotArabicNumerals | ApiError::InvalidJson => {
+                ApiErrorCategory::InvalidRequest
+            }
+            ApiError::InvalidAuthorization | ApiError::InvalidPayoutConfig => {
+                ApiErrorCategory::AuthFailure
+            }
+            ApiError::Unknown(_) => ApiErrorCategory::Unknown,
+        }
     }
 
     pub fn is_success(&self) -> bool {
-        self.0 == 1000
+        *self == ApiError::Success
+    }
+
+    /// The bank hasn't answered yet, the transfer may still complete
+    pub fn is_pending(&self) -> bool {
+        self.category() == ApiErrorCategory::Pending
+    }
+
+    /// Temporary failure, the same request can be sent again later
+    pub fn is_retryable(&self) -> bool {
+        self.category() == ApiErrorCategory::Retryable
+    }
+
+    /// The provider refused the transfer because of the request, the balance or
+    /// the daily limit. Nothing was paid.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self.category(),
+            ApiErrorCategory::InvalidRequest
+                | ApiErrorCategory::InsufficientFunds
+                | ApiErrorCategory::LimitExceeded
+        )
+    }
+
+    /// The transaction won't change its state anymore. Pending, manual, retryable
+    /// and unknown codes are not final.
+    pub fn is_final(&self) -> bool {
+        !matches!(
+            self.category(),
+            ApiErrorCategory::Retryable
+                | ApiErrorCategory::Pending
+                | ApiErrorCategory::ManualReview
+                | ApiErrorCategory::Unknown
+        )
+    }
+}
+
+impl From<i32> for ApiError {
+    fn from(code: i32) -> Self {
+        ApiError::from_code(code)
+    }
+}
+
+impl From<ApiError> for i32 {
+    fn from(value: ApiError) -> Self {
+        value.to_code()
+    }
+}
+
+/// Serialized as the numeric code, so unknown codes survive a round trip
+impl JsonSchema for ApiError {
+    fn schema_name() -> String {
+        "ApiError".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some("Status code of the provider, 1000 is success".to_owned()),
+                ..Default::default()
+            })),
+            instance_type: Some(InstanceType::Integer.into()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl PartialEq for ApiError {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_code() == other.to_code()
+    }
+}
+
+impl Eq for ApiError {}
+
+impl Hash for ApiError {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_code().hash(state);
+    }
+}
+
+impl PartialOrd for ApiError {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ApiError {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.to_code().cmp(&other.to_code())
     }
 }
 
 impl Display for ApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.0 {
+        match self.to_code() {
             1000 => write!(f, "Success"),
             -2000 => write!(f, "Amount over 100,000 THB waiting to transfer, manual transfer"),
             1899 => write!(f, "We cannot process this transaction at the moment (1899)"),
@@ -60,3 +423,68 @@ impl Display for ApiError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_error_codes() {
+        for code in [1000, -2000, 1899, 9003, -1009, 9090, 4242] {
+            assert_eq!(ApiError::from_code(code).to_code(), code);
+        }
+        assert_eq!(ApiError::from_code(4242), ApiError::Unknown(4242));
+        assert_eq!(
+            ApiError::from_code(-1003).category(),
+            ApiErrorCategory::DuplicateSuspected
+        );
+        assert!(ApiError::BankNoResponse.is_retryable());
+        assert!(!ApiError::WaitingBankResponse.is_final());
+        assert!(ApiError::InsufficientBalance.is_final());
+        assert!(ApiError::InsufficientBalance.is_rejection());
+        assert!(!ApiError::ManualTransfer.is_rejection());
+        assert!(!ApiError::from_code(4242).is_rejection());
+    }
+
+    #[test]
+    fn api_error_compared_by_code() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |e: ApiError| {
+            let mut hasher = DefaultHasher::new();
+            e.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(ApiError::Unknown(1000), ApiError::Success);
+        assert_eq!(hash(ApiError::Unknown(1000)), hash(ApiError::Success));
+        assert_eq!(
+            ApiError::Unknown(-1009).cmp(&ApiError::InsufficientBalance),
+            std::cmp::Ordering::Equal
+        );
+        assert_ne!(ApiError::Unknown(4242), ApiError::Unknown(4243));
+        assert!(ApiError::InsufficientBalance < ApiError::Success);
+    }
+
+    #[test]
+    fn response_info_masks_body() {
+        let info = ResponseInfo {
+            status: 200,
+            message: None,
+            body: "{\"bankacc\":\"0652078409\",\"mobileno\":\"0805933181\"}".to_owned(),
+        };
+        let shown = info.to_string();
+        assert!(shown.contains("******8409"), "{shown}");
+        assert!(!shown.contains("0652078409") && !shown.contains("0805933181"));
+        assert!(!format!("{info:?}").contains("0652078409"));
+    }
+
+    #[test]
+    fn api_error_serde() {
+        assert_eq!(
+            serde_json::to_string(&ApiError::ManualTransfer).expect("encoded"),
+            "-2000"
+        );
+        let decoded: ApiError = serde_json::from_str("5009").expect("decoded");
+        assert_eq!(decoded, ApiError::IncorrectAccount);
+    }
+}
@@ -0,0 +1,318 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, Schema, SchemaObject, StringValidation};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Bank;
+
+/// Max length of `ref1` accepted by the API
+pub const REF1_MAX_LEN: usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FieldError {
+    #[error("Bank account must contain only digits: {0}")]
+    AccountNotDigits(String),
+    #[error("Bank account of {bank:?} must have {expected:?} digits, got {length}")]
+    AccountLength {
+        bank: Bank,
+        length: usize,
+        expected: Vec<usize>,
+    },
+    #[error("Not a Thai mobile number: {0}")]
+    InvalidMobile(String),
+    #[error("ref1 {0} field must have length >= 1 and <= 30")]
+    RefLength(String),
+    #[error("ref1 {0} may contain only latin letters, digits, '-' and '_'")]
+    RefCharset(String),
+    #[error("ref1 prefix {0} is longer than 10 characters")]
+    RefPrefix(String),
+    #[error(
+        "ref1 {0} is too long for split parts, it must fit with suffix {1} into 30 characters"
+    )]
+    RefSuffix(String, String),
+}
+
+/// Bank account number, digits only. Example: "0652078409"
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BankAccount(String);
+
+impl BankAccount {
+    /// Validates the number against expected length for the bank. Spaces and
+    /// dashes are dropped, so "065-2-07840-9" is accepted.
+    pub fn new(bank: Bank, number: &str) -> Result<Self, FieldError> {
+        let account = Self::from_digits(number)?;
+        account.check_bank(bank)?;
+        Ok(account)
+    }
+
+    /// Validates only that the number consists of digits. Use when the bank is
+    /// not known yet and call [`BankAccount::check_bank`] later.
+    pub fn from_digits(number: &str) -> Result<Self, FieldError> {
+        let digits: String = number.chars().filter(|c| *c != ' ' && *c != '-').collect();
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(FieldError::AccountNotDigits(number.to_owned()));
+        }
+        Ok(BankAccount(digits))
+    }
+
+    pub fn check_bank(&self, bank: Bank) -> Result<(), FieldError> {
+        let expected = bank.account_lengths();
+        if expected.contains(&self.0.len()) {
+            Ok(())
+        } else {
+            Err(FieldError::AccountLength {
+                bank,
+                length: self.0.len(),
+                expected: expected.to_vec(),
+            })
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for BankAccount {
+    type Error = FieldError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_digits(&value)
+    }
+}
+
+impl From<BankAccount> for String {
+    fn from(value: BankAccount) -> Self {
+        value.0
+    }
+}
+
+impl Display for BankAccount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Thai mobile number in local format. Example: "0805933181"
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ThaiMobile(String);
+
+impl ThaiMobile {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for ThaiMobile {
+    type Err = FieldError;
+
+    /// Accepts "+66 80-593-3181", "66805933181", "080 593 3181" and similar
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || FieldError::InvalidMobile(s.to_owned());
+        let compact: String = s
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '(' | ')' | '.'))
+            .collect();
+        let national = if let Some(rest) = compact.strip_prefix("+66") {
+            rest
+        } else if compact.len() == 11 && compact.starts_with("66") {
+            &compact[2..]
+        } else if let Some(rest) = compact.strip_prefix('0') {
+            rest
+        } else {
+            return Err(err());
+        };
+        let national = national.strip_prefix('0').unwrap_or(national);
+        let mobile_prefix = matches!(national.chars().next(), Some('6' | '8' | '9'));
+        if national.len() != 9 || !mobile_prefix || !national.chars().all(|c| c.is_ascii_digit()) {
+            return Err(err());
+        }
+        Ok(ThaiMobile(format!("0{national}")))
+    }
+}
+
+impl TryFrom<String> for ThaiMobile {
+    type Error = FieldError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ThaiMobile> for String {
+    fn from(value: ThaiMobile) -> Self {
+        value.0
+    }
+}
+
+impl Display for ThaiMobile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// External ID of the transaction, 1 to 30 latin letters, digits, '-' or '_'.
+/// Example: "123456789012345678"
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Ref1(String);
+
+impl Ref1 {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Ref1 {
+    type Err = FieldError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.len() > REF1_MAX_LEN {
+            return Err(FieldError::RefLength(s.to_owned()));
+        }
+        if !s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(FieldError::RefCharset(s.to_owned()));
+        }
+        Ok(Ref1(s.to_owned()))
+    }
+}
+
+impl TryFrom<String> for Ref1 {
+    type Error = FieldError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Ref1> for String {
+    fn from(value: Ref1) -> Self {
+        value.0
+    }
+}
+
+impl Display for Ref1 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn string_schema(description: &str, pattern: &str) -> Schema {
+    SchemaObject {
+        metadata: Some(Box::new(Metadata {
+            description: Some(description.to_owned()),
+            ..Default::default()
+        })),
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some(pattern.to_owned()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+impl JsonSchema for BankAccount {
+    fn schema_name() -> String {
+        "BankAccount".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema("Bank account number, digits only", "^[0-9]+$")
+    }
+}
+
+impl JsonSchema for ThaiMobile {
+    fn schema_name() -> String {
+        "ThaiMobile".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema("Thai mobile number in local format", "^0[689][0-9]{8}$")
+    }
+}
+
+impl JsonSchema for Ref1 {
+    fn schema_name() -> String {
+        "Ref1".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema("External ID of the transaction", "^[A-Za-z0-9_-]{1,30}$")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_account() {
+        assert_eq!(
+            BankAccount::new(Bank::Kasikorn, "065-2-07840-9").map(String::from),
+            Ok("0652078409".to_owned())
+        );
+        assert_eq!(
+            BankAccount::new(Bank::Kasikorn, "66520784099"),
+            Err(FieldError::AccountLength {
+                bank: Bank::Kasikorn,
+                length: 11,
+                expected: vec![10],
+            })
+        );
+        assert!(BankAccount::new(Bank::GovernmentSavings, "020012345678").is_ok());
+        assert!(BankAccount::new(Bank::Islamic, "123456789012345").is_ok());
+        assert!(BankAccount::new(Bank::CimbThai, "123456789").is_err());
+        assert!(matches!(
+            BankAccount::new(Bank::Kasikorn, "06520784O9"),
+            Err(FieldError::AccountNotDigits(_))
+        ));
+    }
+
+    #[test]
+    fn thai_mobile() {
+        for input in [
+            "0805933181",
+            "080-593-3181",
+            "+66 80 593 3181",
+            "+66805933181",
+            "+660805933181",
+            "66805933181",
+        ] {
+            assert_eq!(
+                input.parse::<ThaiMobile>().map(String::from),
+                Ok("0805933181".to_owned()),
+                "{input}"
+            );
+        }
+        for input in ["080593318", "021234567", "+1 805933181", "08O5933181"] {
+            assert!(input.parse::<ThaiMobile>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn ref1() {
+        assert!("6957f82b34aa4e1dab67466fe74804".parse::<Ref1>().is_ok());
+        assert!("order_1-a".parse::<Ref1>().is_ok());
+        assert!(matches!("".parse::<Ref1>(), Err(FieldError::RefLength(_))));
+        assert!(matches!(
+            "6957f82b34aa4e1dab67466fe748041".parse::<Ref1>(),
+            Err(FieldError::RefLength(_))
+        ));
+        assert!(matches!(
+            "order 1".parse::<Ref1>(),
+            Err(FieldError::RefCharset(_))
+        ));
+        assert!(serde_json::from_str::<Ref1>("\"a/b\"").is_err());
+    }
+}
@@ -0,0 +1,609 @@
+//! Client side limits checked before a transfer is sent, so the provider's
+//! daily limit (code 6000) is not hit in the middle of a run. Totals and counts
+//! are kept per Bangkok day for all transfers, per partner code and per
+//! recipient account. A transfer is counted when it is about to be sent and
+//! is taken back only when it certainly wasn't paid.
+
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDate;
+use log::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::Error;
+use crate::journal::open_append;
+use crate::{timestamp, Bank, BankAccount, Ref1, Thb, TransferReq};
+
+#[derive(Debug, Clone, Error)]
+pub enum GuardError {
+    #[error("Limit store IO error: {0}")]
+    Io(Arc<std::io::Error>),
+    #[error("Limit store line {line} is corrupted: {error}")]
+    Corrupted {
+        line: usize,
+        error: Arc<serde_json::Error>,
+    },
+    #[error("Failed to encode limit record: {0}")]
+    Encode(Arc<serde_json::Error>),
+}
+
+/// Maximum total and number of transfers in a day, `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Limit {
+    pub amount: Option<Thb>,
+    pub count: Option<u32>,
+}
+
+impl Limit {
+    fn allows(&self, used: Usage, amount: Thb) -> bool {
+        let total = used.amount.checked_add(amount);
+        self.amount
+            .map_or(true, |max| total.map_or(false, |total| total <= max))
+            && self.count.map_or(true, |max| used.count < max)
+    }
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.amount, self.count) {
+            (Some(amount), Some(count)) => write!(f, "{amount} THB in {count} transfers"),
+            (Some(amount), None) => write!(f, "{amount} THB"),
+            (None, Some(count)) => write!(f, "{count} transfers"),
+            (None, None) => write!(f, "unlimited"),
+        }
+    }
+}
+
+/// Limits of [`LimitGuard`] by scope
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Limits {
+    /// All transfers in the store
+    pub daily: Limit,
+    /// Transfers of one partner code, for stores shared by several partners
+    pub per_partner: Limit,
+    /// Transfers to one bank account
+    pub per_recipient: Limit,
+}
+
+/// What a limit counts. In JSON the kind is in `scope` field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "scope", rename_all = "snake_case")]
+pub enum LimitScope {
+    Daily,
+    Partner { partner_code: String },
+    Recipient { bank: Bank, bankacc: BankAccount },
+}
+
+impl Display for LimitScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitScope::Daily => write!(f, "Daily"),
+            LimitScope::Partner { partner_code } => write!(f, "Partner {partner_code}"),
+            LimitScope::Recipient { bank, bankacc } => {
+                write!(f, "Recipient {bankacc} of {}", bank.to_acronym())
+            }
+        }
+    }
+}
+
+/// Transfers counted in a day
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Usage {
+    pub amount: Thb,
+    pub count: u32,
+}
+
+/// A transfer counted against the limits of its day
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Reservation {
+    /// Bangkok date
+    pub day: NaiveDate,
+    pub ref1: Ref1,
+    pub partner_code: String,
+    pub bank: Bank,
+    pub bankacc: BankAccount,
+    pub amount: Thb,
+}
+
+impl Reservation {
+    fn counts_in(&self, scope: &LimitScope) -> bool {
+        match scope {
+            LimitScope::Daily => true,
+            LimitScope::Partner { partner_code } => self.partner_code == *partner_code,
+            LimitScope::Recipient { bank, bankacc } => {
+                self.bank == *bank && self.bankacc == *bankacc
+            }
+        }
+    }
+}
+
+/// Transfer that would go over a limit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Error)]
+#[error("{scope} limit of {day} is {limit}, already used {} THB in {} transfers, transfer of {amount} THB doesn't fit", used.amount, used.count)]
+pub struct LimitBreach {
+    pub scope: LimitScope,
+    pub day: NaiveDate,
+    pub limit: Limit,
+    pub used: Usage,
+    pub amount: Thb,
+}
+
+/// What to do with a transfer that would go over a limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Fail with [`Error::LimitExceeded`] right away
+    #[default]
+    Reject,
+    /// Wait for the limits to reset at Bangkok midnight if that happens within
+    /// `max_wait`, otherwise reject
+    Queue { max_wait: Duration },
+}
+
+/// Where reservations are kept. One store can be shared by several clients,
+/// e.g. with different partner codes.
+pub trait LimitStore: Debug + Send + Sync {
+    /// Reservations of the day
+    fn reservations(&self, day: NaiveDate) -> Result<Vec<Reservation>, GuardError>;
+
+    fn reserve(&self, reservation: &Reservation) -> Result<(), GuardError>;
+
+    /// Takes the transfer back, it wasn't paid
+    fn release(&self, day: NaiveDate, ref1: &Ref1) -> Result<(), GuardError>;
+}
+
+/// Store kept in memory, limits start from zero when the process restarts
+#[derive(Debug, Default)]
+pub struct MemoryLimitStore {
+    reservations: Mutex<Vec<Reservation>>,
+}
+
+impl MemoryLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LimitStore for MemoryLimitStore {
+    fn reservations(&self, day: NaiveDate) -> Result<Vec<Reservation>, GuardError> {
+        let reservations = self.reservations.lock().expect("limit store lock");
+        Ok(reservations
+            .iter()
+            .filter(|r| r.day == day)
+            .cloned()
+            .collect())
+    }
+
+    fn reserve(&self, reservation: &Reservation) -> Result<(), GuardError> {
+        self.reservations
+            .lock()
+            .expect("limit store lock")
+            .push(reservation.clone());
+        Ok(())
+    }
+
+    fn release(&self, day: NaiveDate, ref1: &Ref1) -> Result<(), GuardError> {
+        self.reservations
+            .lock()
+            .expect("limit store lock")
+            .retain(|r| r.day != day || r.ref1 != *ref1);
+        Ok(())
+    }
+}
+
+/// One line of [`FileLimitStore`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum LimitRecord {
+    Reserve(Reservation),
+    Release { day: NaiveDate, ref1: Ref1 },
+}
+
+/// Store in a file with one JSON record per line, so limits survive restarts.
+/// Records of past days are ignored, the file can be removed after midnight.
+#[derive(Debug)]
+pub struct FileLimitStore {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl FileLimitStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, GuardError> {
+        let path = path.as_ref().to_owned();
+        let file = open_append(&path).map_err(io_error)?;
+        Ok(FileLimitStore {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(&self, record: &LimitRecord) -> Result<(), GuardError> {
+        let mut line = serde_json::to_vec(record).map_err(|e| GuardError::Encode(Arc::new(e)))?;
+        line.push(b'\n');
+        let mut file = self.file.lock().expect("limit store lock");
+        file.write_all(&line).map_err(io_error)?;
+        file.sync_data().map_err(io_error)
+    }
+}
+
+impl LimitStore for FileLimitStore {
+    fn reservations(&self, day: NaiveDate) -> Result<Vec<Reservation>, GuardError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(io_error(e)),
+        };
+        let mut reservations: Vec<Reservation> = vec![];
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(io_error)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(LimitRecord::Reserve(r)) if r.day == day => reservations.push(r),
+                Ok(LimitRecord::Release { day: d, ref1 }) if d == day => {
+                    reservations.retain(|r| r.ref1 != ref1)
+                }
+                Ok(_) => {}
+                Err(e) if e.is_eof() => {
+                    warn!("Skipping incomplete limit store line {}: {e}", i + 1)
+                }
+                Err(e) => {
+                    return Err(GuardError::Corrupted {
+                        line: i + 1,
+                        error: Arc::new(e),
+                    })
+                }
+            }
+        }
+        Ok(reservations)
+    }
+
+    fn reserve(&self, reservation: &Reservation) -> Result<(), GuardError> {
+        self.append(&LimitRecord::Reserve(reservation.clone()))
+    }
+
+    fn release(&self, day: NaiveDate, ref1: &Ref1) -> Result<(), GuardError> {
+        self.append(&LimitRecord::Release {
+            day,
+            ref1: ref1.clone(),
+        })
+    }
+}
+
+fn io_error(e: std::io::Error) -> GuardError {
+    GuardError::Io(Arc::new(e))
+}
+
+/// Checks transfers against [`Limits`] before they are sent, see
+/// [`crate::ClientBuilder::limit_guard`]
+#[derive(Debug)]
+pub struct LimitGuard {
+    limits: Limits,
+    store: Arc<dyn LimitStore>,
+    action: LimitAction,
+    /// Check and reserve of one process happen together
+    lock: Mutex<()>,
+}
+
+impl LimitGuard {
+    pub fn new(limits: Limits, store: Arc<dyn LimitStore>) -> Self {
+        LimitGuard {
+            limits,
+            store,
+            action: LimitAction::default(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Default: [`LimitAction::Reject`]
+    pub fn action(mut self, action: LimitAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Transfers counted in the scope today
+    pub fn usage(&self, scope: &LimitScope) -> Result<Usage, GuardError> {
+        let reservations = self.store.reservations(timestamp::now().date_naive())?;
+        Ok(usage(&reservations, scope))
+    }
+
+    /// Counts the transfer against today's limits or fails with
+    /// [`Error::LimitExceeded`]. A transfer with `ref1` already counted today is
+    /// not counted again and `None` is returned, that reservation belongs to the
+    /// earlier call and must not be released by this one.
+    pub async fn reserve(
+        &self,
+        partner_code: &str,
+        req: &TransferReq,
+    ) -> Result<Option<Reservation>, Error> {
+        let started = Instant::now();
+        loop {
+            let now = timestamp::now();
+            let reservation = Reservation {
+                day: now.date_naive(),
+                ref1: req.ref1.clone(),
+                partner_code: partner_code.to_owned(),
+                bank: req.bank,
+                bankacc: req.bankacc.clone(),
+                amount: req.amount,
+            };
+            let breach = match self.try_reserve(&reservation) {
+                Ok(true) => return Ok(Some(reservation)),
+                Ok(false) => return Ok(None),
+                Err(Error::LimitExceeded(breach)) => breach,
+                Err(e) => return Err(e),
+            };
+            let LimitAction::Queue { max_wait } = self.action else {
+                return Err(Error::LimitExceeded(breach));
+            };
+            // A transfer larger than the limit itself won't fit tomorrow either
+            if !breach.limit.allows(Usage::default(), breach.amount) {
+                return Err(Error::LimitExceeded(breach));
+            }
+            let midnight = reservation
+                .day
+                .succ_opt()
+                .and_then(|day| day.and_hms_opt(0, 0, 0))
+                .map(timestamp::from_local)
+                .expect("next day exists");
+            let wait = (midnight - now).to_std().unwrap_or_default();
+            if started.elapsed() + wait > max_wait {
+                return Err(Error::LimitExceeded(breach));
+            }
+            info!("{breach}. Waiting {wait:?} for the limit to reset");
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// True if the reservation was added, false if its `ref1` was counted already
+    fn try_reserve(&self, reservation: &Reservation) -> Result<bool, Error> {
+        let _lock = self.lock.lock().expect("limit guard lock");
+        let reservations = self
+            .store
+            .reservations(reservation.day)
+            .map_err(Error::Guard)?;
+        if reservations.iter().any(|r| r.ref1 == reservation.ref1) {
+            return Ok(false);
+        }
+        let scopes = [
+            (LimitScope::Daily, self.limits.daily),
+            (
+                LimitScope::Partner {
+                    partner_code: reservation.partner_code.clone(),
+                },
+                self.limits.per_partner,
+            ),
+            (
+                LimitScope::Recipient {
+                    bank: reservation.bank,
+                    bankacc: reservation.bankacc.clone(),
+                },
+                self.limits.per_recipient,
+            ),
+        ];
+        for (scope, limit) in scopes {
+            let used = usage(&reservations, &scope);
+            if !limit.allows(used, reservation.amount) {
+                return Err(Error::LimitExceeded(LimitBreach {
+                    scope,
+                    day: reservation.day,
+                    limit,
+                    used,
+                    amount: reservation.amount,
+                }));
+            }
+        }
+        self.store.reserve(reservation).map_err(Error::Guard)?;
+        Ok(true)
+    }
+
+    /// Takes the transfer back, it wasn't paid. Failure to release is only
+    /// logged, the limit stays more strict than needed.
+    pub fn release(&self, reservation: &Reservation) {
+        if let Err(e) = self.store.release(reservation.day, &reservation.ref1) {
+            warn!("Failed to release limit of {}: {e}", reservation.ref1);
+        }
+    }
+}
+
+fn usage(reservations: &[Reservation], scope: &LimitScope) -> Usage {
+    reservations
+        .iter()
+        .filter(|r| r.counts_in(scope))
+        .fold(Usage::default(), |used, r| Usage {
+            amount: used
+                .amount
+                .checked_add(r.amount)
+                .unwrap_or(Thb::from_satang(i64::MAX)),
+            count: used.count.saturating_add(1),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+    use crate::Client;
+    use serde_json::json;
+
+    fn request(ref1: &str, bankacc: &str, amount: i64) -> TransferReq {
+        TransferReq::builder()
+            .bankacc(bankacc)
+            .bank(Bank::Kasikorn)
+            .accname("Manop Tangngam")
+            .amount(Thb::from_baht(amount).expect("amount"))
+            .mobileno("0805933181")
+            .transaction_by("Jack Developer")
+            .ref1(ref1)
+            .build()
+            .expect("request")
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            daily: Limit {
+                amount: Some(Thb::from_baht(100).expect("amount")),
+                count: None,
+            },
+            per_partner: Limit::default(),
+            per_recipient: Limit {
+                amount: None,
+                count: Some(2),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_are_enforced() {
+        let guard = LimitGuard::new(limits(), Arc::new(MemoryLimitStore::new()));
+        let reserve = |ref1, bankacc, amount| {
+            let req = request(ref1, bankacc, amount);
+            let guard = &guard;
+            async move { guard.reserve("CRS", &req).await }
+        };
+        reserve("a-1", "0652078409", 10).await.expect("fits");
+        reserve("a-2", "0652078409", 10).await.expect("fits");
+        // Counted already
+        assert_eq!(
+            reserve("a-2", "0652078409", 10).await.expect("same ref1"),
+            None
+        );
+        let err = reserve("a-3", "0652078409", 10).await.expect_err("third");
+        assert!(matches!(
+            &err,
+            Error::LimitExceeded(LimitBreach {
+                scope: LimitScope::Recipient { .. },
+                used: Usage { count: 2, .. },
+                ..
+            })
+        ));
+
+        let err = reserve("b-1", "1234567890", 81).await.expect_err("over");
+        let Error::LimitExceeded(breach) = err else {
+            panic!("limit error expected");
+        };
+        assert_eq!(breach.scope, LimitScope::Daily);
+        assert_eq!(breach.used.amount, Thb::from_baht(20).expect("amount"));
+        reserve("b-2", "1234567890", 80).await.expect("fits");
+        assert_eq!(
+            guard.usage(&LimitScope::Daily).expect("usage").amount,
+            Thb::from_baht(100).expect("amount")
+        );
+    }
+
+    #[tokio::test]
+    async fn queue_gives_up_after_max_wait() {
+        let guard = LimitGuard::new(limits(), Arc::new(MemoryLimitStore::new())).action(
+            LimitAction::Queue {
+                max_wait: Duration::from_millis(1),
+            },
+        );
+        let res = guard
+            .reserve("CRS", &request("a-1", "0652078409", 101))
+            .await;
+        assert!(matches!(res, Err(Error::LimitExceeded(_))));
+    }
+
+    #[tokio::test]
+    async fn client_releases_unpaid_transfers() {
+        let store = Arc::new(MemoryLimitStore::new());
+        let guard = Arc::new(LimitGuard::new(limits(), store.clone()));
+        let mock = Arc::new(MockTransport::new());
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock.clone())
+            .limit_guard(guard.clone())
+            .build()
+            .expect("client");
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        let res = client.transfer(request("a-1", "0652078409", 60)).await;
+        assert_eq!(
+            res.err().and_then(|e| e.api_code()),
+            Some(crate::error::ApiError::InsufficientBalance)
+        );
+        assert_eq!(
+            guard.usage(&LimitScope::Daily).expect("usage"),
+            Usage::default()
+        );
+
+        mock.push_transfer(json!({"status": 9090, "message": "Waiting"}));
+        client
+            .transfer(request("a-2", "0652078409", 60))
+            .await
+            .expect_err("pending");
+        // Unpaid repeat of a counted ref1 keeps the earlier reservation
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        client
+            .transfer(request("a-2", "0652078409", 60))
+            .await
+            .expect_err("not paid");
+        assert_eq!(guard.usage(&LimitScope::Daily).expect("usage").count, 1);
+        let res = client.transfer(request("a-3", "0652078409", 60)).await;
+        assert!(matches!(res, Err(Error::LimitExceeded(_))), "{res:?}");
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn client_keeps_reservation_of_unanswered_transfer() {
+        let guard = Arc::new(LimitGuard::new(limits(), Arc::new(MemoryLimitStore::new())));
+        let mock = Arc::new(MockTransport::new());
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock.clone())
+            .limit_guard(guard.clone())
+            .build()
+            .expect("client");
+        mock.push_transfer(json!({"status": 9091, "message": "no response"}));
+        client
+            .transfer(request("a-1", "0652078409", 60))
+            .await
+            .expect_err("no response");
+        assert_eq!(guard.usage(&LimitScope::Daily).expect("usage").count, 1);
+    }
+
+    #[test]
+    fn file_store_survives_restart() {
+        let path =
+            std::env::temp_dir().join(format!("one-two-pay-limits-{}.jsonl", Ref1::generate()));
+        let day = NaiveDate::from_ymd_opt(2022, 5, 17).expect("date");
+        let reservation = |ref1: &str| Reservation {
+            day,
+            ref1: ref1.parse().expect("ref1"),
+            partner_code: "CRS".to_owned(),
+            bank: Bank::Kasikorn,
+            bankacc: BankAccount::from_digits("0652078409").expect("account"),
+            amount: Thb::from_satang(100),
+        };
+        let store = FileLimitStore::open(&path).expect("opened");
+        store.reserve(&reservation("a-1")).expect("reserved");
+        store.reserve(&reservation("a-2")).expect("reserved");
+        store
+            .release(day, &"a-1".parse().expect("ref1"))
+            .expect("released");
+        drop(store);
+
+        let store = FileLimitStore::open(&path).expect("reopened");
+        assert_eq!(
+            store.reservations(day).expect("read"),
+            vec![reservation("a-2")]
+        );
+        assert!(store
+            .reservations(day.succ_opt().expect("day"))
+            .expect("read")
+            .is_empty());
+        std::fs::remove_file(&path).expect("removed");
+    }
+}
@@ -0,0 +1,347 @@
+//! Durable record of payouts. An intent is written before the transfer is sent
+//! and the outcome after it, so after a crash the transfers that were in flight
+//! can be found and resolved by querying their `ref1`.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use log::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{PayoutOutcome, Ref1, TransferReq};
+
+#[derive(Debug, Clone, Error)]
+pub enum JournalError {
+    #[error("Journal IO error: {0}")]
+    Io(Arc<std::io::Error>),
+    #[error("Journal line {line} is corrupted: {error}")]
+    Corrupted {
+        line: usize,
+        error: Arc<serde_json::Error>,
+    },
+    #[error("Failed to encode journal record: {0}")]
+    Encode(Arc<serde_json::Error>),
+}
+
+/// One line of the journal. In JSON the kind is in `event` field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalRecord {
+    /// Written before the transfer is sent
+    Intent {
+        at: DateTime<Utc>,
+        request: Box<TransferReq>,
+    },
+    /// What became of the transfer. `Unknown` outcome keeps it in flight.
+    Outcome {
+        at: DateTime<Utc>,
+        ref1: Ref1,
+        outcome: PayoutOutcome,
+    },
+    /// The transfer was certainly not sent, e.g. it failed validation
+    Aborted {
+        at: DateTime<Utc>,
+        ref1: Ref1,
+        error: String,
+    },
+}
+
+impl JournalRecord {
+    pub fn ref1(&self) -> &Ref1 {
+        match self {
+            JournalRecord::Intent { request, .. } => &request.ref1,
+            JournalRecord::Outcome { ref1, .. } | JournalRecord::Aborted { ref1, .. } => ref1,
+        }
+    }
+}
+
+/// Latest known state of a payout
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct JournalEntry {
+    pub request: TransferReq,
+    pub started_at: DateTime<Utc>,
+    /// `None` if the process stopped before the outcome was written
+    pub outcome: Option<PayoutOutcome>,
+}
+
+impl JournalEntry {
+    /// Money may be moving, the entry has to be resolved by query
+    pub fn is_in_flight(&self) -> bool {
+        matches!(self.outcome, None | Some(PayoutOutcome::Unknown { .. }))
+    }
+}
+
+/// Where journal records are kept. Appended record must be durable when
+/// `append` returns.
+pub trait JournalStore: Debug + Send + Sync {
+    fn append(&self, record: &JournalRecord) -> Result<(), JournalError>;
+
+    /// All records in the order they were appended
+    fn records(&self) -> Result<Vec<JournalRecord>, JournalError>;
+
+    /// Entries by `ref1` built from the records
+    fn entries(&self) -> Result<BTreeMap<Ref1, JournalEntry>, JournalError> {
+        let mut entries: BTreeMap<Ref1, JournalEntry> = BTreeMap::new();
+        for record in self.records()? {
+            match record {
+                JournalRecord::Intent { at, request } => {
+                    entries.insert(
+                        request.ref1.clone(),
+                        JournalEntry {
+                            request: *request,
+                            started_at: at,
+                            outcome: None,
+                        },
+                    );
+                }
+                JournalRecord::Outcome { ref1, outcome, .. } => {
+                    if let Some(entry) = entries.get_mut(&ref1) {
+                        entry.outcome = Some(outcome);
+                    }
+                }
+                JournalRecord::Aborted { ref1, .. } => {
+                    entries.remove(&ref1);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Payouts that were started but have no final outcome
+    fn in_flight(&self) -> Result<Vec<JournalEntry>, JournalError> {
+        Ok(self
+            .entries()?
+            .into_values()
+            .filter(JournalEntry::is_in_flight)
+            .collect())
+    }
+}
+
+/// Journal kept in memory, for tests and processes that can afford to lose it
+#[derive(Debug, Default)]
+pub struct MemoryJournal {
+    records: Mutex<Vec<JournalRecord>>,
+}
+
+impl MemoryJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl JournalStore for MemoryJournal {
+    fn append(&self, record: &JournalRecord) -> Result<(), JournalError> {
+        self.records
+            .lock()
+            .expect("journal lock")
+            .push(record.clone());
+        Ok(())
+    }
+
+    fn records(&self) -> Result<Vec<JournalRecord>, JournalError> {
+        Ok(self.records.lock().expect("journal lock").clone())
+    }
+}
+
+/// Journal in a file with one JSON record per line. Every record is flushed to
+/// disk before `append` returns.
+#[derive(Debug)]
+pub struct FileJournal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl FileJournal {
+    /// Opens the file for appending, creating it if needed. A line cut short by
+    /// a crash is terminated, so new records start on their own line.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_owned();
+        let file = open_append(&path).map_err(io_error)?;
+        Ok(FileJournal {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl JournalStore for FileJournal {
+    fn append(&self, record: &JournalRecord) -> Result<(), JournalError> {
+        let mut line = serde_json::to_vec(record).map_err(|e| JournalError::Encode(Arc::new(e)))?;
+        line.push(b'\n');
+        let mut file = self.file.lock().expect("journal lock");
+        file.write_all(&line).map_err(io_error)?;
+        file.sync_data().map_err(io_error)
+    }
+
+    /// Lines that are cut short are skipped, that is how a crash in the middle of
+    /// `append` looks like. Any other broken line is an error.
+    fn records(&self) -> Result<Vec<JournalRecord>, JournalError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(io_error(e)),
+        };
+        let lines = BufReader::new(file)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io_error)?;
+        let mut records = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) if e.is_eof() => {
+                    warn!("Skipping incomplete journal line {}: {e}", i + 1);
+                }
+                Err(e) => {
+                    return Err(JournalError::Corrupted {
+                        line: i + 1,
+                        error: Arc::new(e),
+                    })
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Opens a JSON lines file for appending. A line cut short by a crash is
+/// terminated, so new lines are not glued to it.
+pub(crate) fn open_append(path: &Path) -> std::io::Result<File> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    let len = file.metadata()?.len();
+    if len > 0 {
+        let mut last = [0u8];
+        file.seek(SeekFrom::Start(len - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+            file.sync_data()?;
+        }
+    }
+    Ok(file)
+}
+
+fn io_error(e: std::io::Error) -> JournalError {
+    JournalError::Io(Arc::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiError;
+
+    fn request(ref1: &str) -> TransferReq {
+        TransferReq::builder()
+            .bankacc("0652078409")
+            .bank(crate::Bank::Kasikorn)
+            .accname("Manop Tangngam")
+            .amount_str("1.00")
+            .mobileno("0805933181")
+            .transaction_by("Jack Developer")
+            .ref1(ref1)
+            .build()
+            .expect("request")
+    }
+
+    fn intent(ref1: &str) -> JournalRecord {
+        JournalRecord::Intent {
+            at: Utc::now(),
+            request: Box::new(request(ref1)),
+        }
+    }
+
+    fn outcome(ref1: &str, outcome: PayoutOutcome) -> JournalRecord {
+        JournalRecord::Outcome {
+            at: Utc::now(),
+            ref1: ref1.parse().expect("ref1"),
+            outcome,
+        }
+    }
+
+    fn in_flight(store: &dyn JournalStore) -> Vec<String> {
+        store
+            .in_flight()
+            .expect("in flight")
+            .into_iter()
+            .map(|e| e.request.ref1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn entries_track_latest_state() {
+        let store = MemoryJournal::new();
+        for record in [
+            intent("done"),
+            intent("crashed"),
+            intent("unknown"),
+            intent("invalid"),
+            outcome(
+                "done",
+                PayoutOutcome::Failed {
+                    code: ApiError::InsufficientBalance,
+                    message: None,
+                },
+            ),
+            outcome(
+                "unknown",
+                PayoutOutcome::Unknown {
+                    last_status: None,
+                    message: None,
+                },
+            ),
+            JournalRecord::Aborted {
+                at: Utc::now(),
+                ref1: "invalid".parse().expect("ref1"),
+                error: "Invalid field".to_owned(),
+            },
+        ] {
+            store.append(&record).expect("appended");
+        }
+        assert_eq!(in_flight(&store), vec!["crashed", "unknown"]);
+        assert_eq!(store.entries().expect("entries").len(), 3);
+    }
+
+    #[test]
+    fn file_journal_survives_torn_write() {
+        let path =
+            std::env::temp_dir().join(format!("one-two-pay-journal-{}.jsonl", Ref1::generate()));
+        let store = FileJournal::open(&path).expect("opened");
+        store.append(&intent("first")).expect("appended");
+        store.append(&intent("second")).expect("appended");
+        drop(store);
+        let mut file = OpenOptions::new().append(true).open(&path).expect("file");
+        file.write_all(b"{\"event\":\"outcome\",\"at\":")
+            .expect("written");
+
+        let store = FileJournal::open(&path).expect("reopened");
+        store
+            .append(&outcome(
+                "first",
+                PayoutOutcome::NeedsManualReview {
+                    code: ApiError::ManualTransfer,
+                    message: None,
+                },
+            ))
+            .expect("appended");
+        assert_eq!(in_flight(&store), vec!["second"]);
+        std::fs::remove_file(&path).expect("removed");
+    }
+}
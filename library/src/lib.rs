@@ -1,84 +1,316 @@
 pub mod bank;
+pub mod batch;
+pub mod builder;
+mod de;
+pub mod dedup;
 pub mod error;
+pub mod fields;
+pub mod guard;
+pub mod journal;
+pub mod money;
+pub mod payout;
+pub mod qr;
 pub mod query;
+pub mod reconcile;
+pub mod records;
+pub mod ref_gen;
+pub mod retry;
+pub mod schema;
+pub mod secret;
+pub mod timestamp;
 pub mod transfer;
+pub mod transport;
+pub mod validation;
 
 pub use bank::*;
-use error::Error;
+pub use batch::{BatchRunner, BatchSummary};
+pub use builder::ClientBuilder;
+pub use dedup::DuplicateDetector;
+use error::{ApiError, Error, ResponseInfo};
+pub use fields::{BankAccount, Ref1, ThaiMobile};
+pub use guard::{FileLimitStore, LimitGuard, LimitStore, Limits, MemoryLimitStore};
+pub use journal::{FileJournal, JournalStore, MemoryJournal};
 use log::*;
-use query::QueryResInner;
-pub use query::{QueryReq, QueryRes};
+pub use money::Thb;
+pub use payout::{PayoutOrchestrator, PayoutOutcome};
+pub use query::{QueryOutcome, QueryRecord, QueryReq, QueryRes};
+use query::{QueryResError, QueryResInner};
+pub use reconcile::{ReconcileReport, Reconciler};
+pub use ref_gen::Ref1Generator;
+pub use retry::RetryPolicy;
+pub use secret::Secret;
+use serde::{de::DeserializeOwned, Serialize};
 use std::sync::Arc;
-use transfer::TransferResInner;
+use std::time::Instant;
+use transfer::{TransferConvError, TransferResInner};
 pub use transfer::{TransferReq, TransferRes};
+pub use transport::{MockTransport, ReqwestTransport, Transport};
+pub use validation::{TransferReqBuilder, ValidationReport};
 
 use crate::transfer::TransferReqInner;
+use crate::transport::{HttpRequest, TransportError};
 
 pub const ONE_TWO_PAY_URL: &str = "https://payout.1-2-pay.com";
+pub const PAYOUT_PATH: &str = "/payout";
+pub const QUERY_PATH: &str = "/inquery-trans";
 
 #[derive(Debug, Clone)]
 pub struct Client {
-    base_url: String,
     channel: String,
     partnercode: String,
-    api_key: String,
+    /// Bearer JWT, redacted in `Debug`
+    api_key: Secret,
+    /// Shared by all calls, so connections are reused
+    transport: Arc<dyn Transport>,
+    retry_policy: RetryPolicy,
+    limit_guard: Option<Arc<LimitGuard>>,
+    duplicate_detector: Option<Arc<DuplicateDetector>>,
+    /// Status of `/inquery-trans` for unknown `ref1`
+    not_found_code: Option<ApiError>,
 }
 
 impl Client {
     pub fn new(channel: &str, partner_code: &str, api_key: &str) -> Self {
+        Self::with_transport(
+            channel,
+            partner_code,
+            api_key,
+            Arc::new(ReqwestTransport::default()),
+        )
+    }
+
+    /// Create client that sends requests via custom transport, e.g. [`MockTransport`]
+    pub fn with_transport(
+        channel: &str,
+        partner_code: &str,
+        api_key: &str,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
         Client {
-            base_url: ONE_TWO_PAY_URL.to_owned(),
             partnercode: partner_code.to_owned(),
             channel: channel.to_owned(),
-            api_key: api_key.to_owned(),
+            api_key: Secret::new(api_key),
+            transport,
+            retry_policy: RetryPolicy::none(),
+            limit_guard: None,
+            duplicate_detector: None,
+            not_found_code: None,
         }
     }
 
+    /// Retry network failures and temporary API errors with the policy
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Check every transfer against the limits of the guard before it is sent
+    pub fn with_limit_guard(mut self, guard: Arc<LimitGuard>) -> Self {
+        self.limit_guard = Some(guard);
+        self
+    }
+
+    /// Refuse transfers that repeat a recent one, see [`DuplicateDetector`]
+    pub fn with_duplicate_detector(mut self, detector: Arc<DuplicateDetector>) -> Self {
+        self.duplicate_detector = Some(detector);
+        self
+    }
+
+    /// Treat the query status as "no transaction with the `ref1`", see
+    /// [`ClientBuilder::not_found_code`]
+    pub fn with_not_found_code(mut self, code: ApiError) -> Self {
+        self.not_found_code = Some(code);
+        self
+    }
+
+    /// Start configuring a client with custom base URL, timeouts, proxy etc.
+    pub fn builder(channel: &str, partner_code: &str, api_key: &str) -> ClientBuilder {
+        ClientBuilder::new(channel, partner_code, api_key)
+    }
+
+    /// Send money to the bank account. When retries are enabled, a request that
+    /// certainly wasn't delivered is sent again right away. Otherwise the retry first
+    /// queries `ref1`, and the transfer is sent again only if the provider has no
+    /// record of it. A final rejection found by the query is returned as
+    /// [`Error::Api`]. Any other answer keeps it querying, and when the retry
+    /// policy runs out the error is [`Error::OutcomeUnknown`], so money is never
+    /// paid twice.
     pub async fn transfer(&self, args: TransferReq) -> Result<TransferRes, Error> {
-        let ref1len = args.ref1.len();
-        if ref1len < 1 || ref1len > 30 {
-            return Err(Error::RefLength(args.ref1));
+        args.bankacc.check_bank(args.bank).map_err(Error::Field)?;
+        if !args.amount.is_positive() {
+            return Err(Error::NotPositiveAmount(args.amount));
+        }
+        let remembered = match &self.duplicate_detector {
+            Some(detector) => detector.check(&args).map_err(Error::DuplicateSuspected)?,
+            None => false,
+        };
+        let ref1 = args.ref1.clone();
+        let res = match &self.limit_guard {
+            Some(guard) => match guard.reserve(&self.partnercode, &args).await {
+                Ok(reservation) => {
+                    let res = self.send_transfer(args).await;
+                    // Only the reservation made by this call is taken back
+                    if let (Some(reservation), Err(e)) = (&reservation, &res) {
+                        if e.is_unpaid() {
+                            guard.release(reservation);
+                        }
+                    }
+                    res
+                }
+                Err(e) => Err(e),
+            },
+            None => self.send_transfer(args).await,
+        };
+        if let (Some(detector), Err(e)) = (&self.duplicate_detector, &res) {
+            if remembered && e.is_unpaid() {
+                detector.forget(&ref1);
+            }
         }
-        let client = reqwest::Client::new();
+        res
+    }
+
+    async fn send_transfer(&self, args: TransferReq) -> Result<TransferRes, Error> {
+        let query = QueryReq {
+            ref1: args.ref1.to_string(),
+        };
         let body: TransferReqInner = args.into();
-        trace!("Body: {:?}", serde_json::to_string(&body));
-        let req = client
-            .post(format!("{}/payout", self.base_url))
-            .header("Authorization", &self.api_key)
-            .header("Partnercode", &self.partnercode)
-            .header("Channel", &self.channel)
-            .json(&body)
-            .build()
-            .map_err(|e| Error::Reqwest(Arc::new(e)))?;
-        let res: TransferResInner = client
-            .execute(req)
-            .await
-            .map_err(|e| Error::Reqwest(Arc::new(e)))?
-            .json()
-            .await
-            .map_err(|e| Error::Reqwest(Arc::new(e)))?;
-        trace!("Response: {:?}", res);
-        let res_conv: TransferRes = res.try_into().map_err(Error::ConvertTransfer)?;
-        Ok(res_conv)
+        let started = Instant::now();
+        let mut attempt = 1;
+        let mut res = self.transfer_once(&body).await;
+        // Until some attempt may have reached the provider, the transfer can be
+        // sent again without checking
+        let mut maybe_sent = false;
+        // The last error came from a query that didn't tell what happened to the
+        // transfer, it is checked again while the policy allows
+        let mut unresolved = false;
+        loop {
+            let err = match res {
+                Ok(res) => return Ok(res),
+                Err(err) => err,
+            };
+            maybe_sent |= !matches!(err, Error::NotDelivered(_));
+            attempt += 1;
+            let retryable = unresolved || err.is_retryable();
+            if !retryable || !self.retry_policy.allows(attempt, started.elapsed()) {
+                return Err(match err {
+                    Error::NotDelivered(e) | Error::OutcomeUnknown(e) if maybe_sent => {
+                        Error::OutcomeUnknown(e)
+                    }
+                    err if unresolved => Error::OutcomeUnknown(TransportError::NoResponse(
+                        format!("transfer {} may have been sent, {err}", query.ref1),
+                    )),
+                    err => err,
+                });
+            }
+            let delay = self.retry_policy.backoff(attempt);
+            debug!(
+                "Transfer {} failed: {err}. Trying again in {delay:?}",
+                query.ref1
+            );
+            tokio::time::sleep(delay).await;
+            if !maybe_sent {
+                res = self.transfer_once(&body).await;
+                continue;
+            }
+
+            // Only a missing ref1 means the transfer can be sent again. Final
+            // rejections, pending, manual and duplicate statuses are returned as
+            // they are, the rest is checked again.
+            unresolved = false;
+            res = match self.query_once(&query).await {
+                Ok((QueryOutcome::Success(found), _)) => Ok(found.into()),
+                Ok((QueryOutcome::NotFound { .. }, _)) => self.transfer_once(&body).await,
+                Ok((QueryOutcome::Failed { code, .. }, info)) if code.is_rejection() => {
+                    Err(Error::Api(code, info))
+                }
+                Ok((QueryOutcome::Pending { .. }, info)) => {
+                    Err(Error::Api(ApiError::WaitingBankResponse, info))
+                }
+                Ok((QueryOutcome::NeedsReview { code, .. }, info)) => Err(Error::Api(code, info)),
+                Ok((QueryOutcome::Failed { code, .. }, info)) => {
+                    unresolved = true;
+                    Err(Error::Api(code, info))
+                }
+                Err(e) => {
+                    unresolved = true;
+                    Err(e)
+                }
+            };
+        }
     }
 
-    pub async fn query(&self, body: QueryReq) -> Result<QueryRes, Error> {
-        let client = reqwest::Client::new();
-        trace!("Body: {:?}", serde_json::to_string(&body));
-        let res: QueryResInner = client
-            .post(format!("{}/inquery-trans", self.base_url))
-            .header("Authorization", &self.api_key)
-            .header("Partnercode", &self.partnercode)
-            .header("Channel", &self.channel)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| Error::Reqwest(Arc::new(e)))?
-            .json()
-            .await
-            .map_err(|e| Error::Reqwest(Arc::new(e)))?;
-        trace!("Response: {:?}", res);
-        let res_conv: QueryRes = res.try_into().map_err(Error::ConvertQuery)?;
+    async fn transfer_once(&self, body: &TransferReqInner) -> Result<TransferRes, Error> {
+        let (res, mut info): (TransferResInner, _) = self.post(PAYOUT_PATH, body).await?;
+        info.message = Some(res.message().to_owned());
+        let res_conv: TransferRes = res.try_into().map_err(|e| match e {
+            TransferConvError::Api(code) => Error::Api(code, info),
+            e => Error::ConvertTransfer(e),
+        })?;
         Ok(res_conv)
     }
+
+    /// Find the transaction by `ref1`. Failed, pending and unknown transactions are
+    /// returned as [`QueryOutcome`], errors are only for requests that couldn't be
+    /// answered.
+    pub async fn query(&self, body: QueryReq) -> Result<QueryOutcome, Error> {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            let err = match self.query_once(&body).await {
+                Ok((res, _)) => return Ok(res),
+                Err(err) => err,
+            };
+            attempt += 1;
+            if !err.is_retryable() || !self.retry_policy.allows(attempt, started.elapsed()) {
+                return Err(err);
+            }
+            tokio::time::sleep(self.retry_policy.backoff(attempt)).await;
+        }
+    }
+
+    async fn query_once(&self, body: &QueryReq) -> Result<(QueryOutcome, ResponseInfo), Error> {
+        let (res, mut info): (QueryResInner, _) = self.post(QUERY_PATH, body).await?;
+        info.message = Some(res.message().to_owned());
+        match QueryOutcome::from_response(res, self.not_found_code) {
+            Ok(outcome) => Ok((outcome, info)),
+            Err(QueryResError::ApiError(code)) => Err(Error::Api(code, info)),
+            Err(e) => Err(Error::ConvertQuery(e)),
+        }
+    }
+
+    async fn post<B, R>(&self, path: &str, body: &B) -> Result<(R, ResponseInfo), Error>
+    where
+        B: Serialize,
+        R: DeserializeOwned,
+    {
+        let body = serde_json::to_string(body).map_err(|e| Error::Json(Arc::new(e)))?;
+        trace!("Body: {}", secret::masked_body(&body));
+        let req = HttpRequest {
+            path: path.to_owned(),
+            authorization: self.api_key.clone(),
+            headers: vec![
+                ("Partnercode".to_owned(), self.partnercode.clone()),
+                ("Channel".to_owned(), self.channel.clone()),
+            ],
+            body,
+        };
+        let raw = self.transport.post(req).await.map_err(|e| {
+            if e.is_not_delivered() {
+                Error::NotDelivered(e)
+            } else {
+                Error::OutcomeUnknown(e)
+            }
+        })?;
+        let info = ResponseInfo {
+            status: raw.status,
+            message: None,
+            body: raw.body,
+        };
+        let res: R = match serde_json::from_str(&info.body) {
+            Ok(res) => res,
+            Err(e) => return Err(Error::Decode(Arc::new(e), info)),
+        };
+        trace!("Response: {}", secret::masked_body(&info.body));
+        Ok((res, info))
+    }
 }
@@ -0,0 +1,282 @@
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, Schema, SchemaObject, StringValidation};
+use schemars::JsonSchema;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
+use thiserror::Error;
+
+const SATANG_IN_BAHT: i64 = 100;
+
+/// Exact amount of Thai baht with satang precision. Serialized as a string with
+/// exactly two decimals, e.g. "1000.50", and read from numbers and strings. The
+/// provider gets a number, see [`as_number`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Thb(i64);
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MoneyError {
+    #[error("Amount is empty")]
+    Empty,
+    #[error("Amount is not a decimal number: {0}")]
+    Invalid(String),
+    #[error("Amount has fractions of satang: {0}")]
+    SubSatang(String),
+    #[error("Amount is too large: {0}")]
+    Overflow(String),
+}
+
+impl Thb {
+    pub const ZERO: Thb = Thb(0);
+
+    pub const fn from_satang(satang: i64) -> Self {
+        Thb(satang)
+    }
+
+    pub fn from_baht(baht: i64) -> Option<Self> {
+        baht.checked_mul(SATANG_IN_BAHT).map(Thb)
+    }
+
+    pub fn satang(self) -> i64 {
+        self.0
+    }
+
+    /// Positive amount can be transferred. Zero and negative can't.
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn checked_add(self, other: Thb) -> Option<Thb> {
+        self.0.checked_add(other.0).map(Thb)
+    }
+
+    pub fn checked_sub(self, other: Thb) -> Option<Thb> {
+        self.0.checked_sub(other.0).map(Thb)
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Option<Thb> {
+        self.0.checked_mul(factor).map(Thb)
+    }
+
+    /// Converts float only if it has no fractions of satang
+    pub fn from_f64(value: f64) -> Result<Self, MoneyError> {
+        if !value.is_finite() {
+            return Err(MoneyError::Invalid(value.to_string()));
+        }
+        // Shortest representation that round trips, so 1000.5 stays "1000.5" and
+        // 0.1 + 0.2 becomes "0.30000000000000004" which has sub satang part.
+        format!("{value}").parse()
+    }
+}
+
+impl FromStr for Thb {
+    type Err = MoneyError;
+
+    /// Parses "1000.50", "1,000.5", "-12" and similar strings
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MoneyError::Invalid(s.to_owned());
+        let overflow = || MoneyError::Overflow(s.to_owned());
+        let trimmed = s.trim();
+        if trimmed.is_empty() {
+            return Err(MoneyError::Empty);
+        }
+        let (negative, unsigned) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        let whole = whole.replace(',', "");
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !whole.chars().all(|c| c.is_ascii_digit())
+            || !fraction.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let (satang_digits, rest) = fraction.split_at(fraction.len().min(2));
+        if rest.chars().any(|c| c != '0') {
+            return Err(MoneyError::SubSatang(s.to_owned()));
+        }
+
+        let baht: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| overflow())?
+        };
+        let satang: i64 = format!("{satang_digits:0<2}")
+            .parse()
+            .map_err(|_| invalid())?;
+        let total = baht
+            .checked_mul(SATANG_IN_BAHT)
+            .and_then(|v| v.checked_add(satang))
+            .ok_or_else(overflow)?;
+        Ok(Thb(if negative { -total } else { total }))
+    }
+}
+
+impl Display for Thb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let satang = SATANG_IN_BAHT as u64;
+        write!(f, "{sign}{}.{:02}", abs / satang, abs % satang)
+    }
+}
+
+impl Debug for Thb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Thb({self})")
+    }
+}
+
+impl Serialize for Thb {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Writes the amount as a JSON number with exactly two decimals, the way the
+/// provider expects it in request bodies. Only for bodies encoded by serde_json.
+pub(crate) fn as_number<S: Serializer>(amount: &Thb, serializer: S) -> Result<S::Ok, S::Error> {
+    let raw = RawValue::from_string(amount.to_string()).map_err(serde::ser::Error::custom)?;
+    raw.serialize(serializer)
+}
+
+/// Accepts JSON numbers and strings like "100,001.00" that inquery returns
+impl<'de> Deserialize<'de> for Thb {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ThbVisitor;
+
+        impl<'de> Visitor<'de> for ThbVisitor {
+            type Value = Thb;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "amount of THB as number or string")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Thb, E> {
+                Thb::from_baht(v).ok_or_else(|| E::custom(MoneyError::Overflow(v.to_string())))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Thb, E> {
+                i64::try_from(v)
+                    .ok()
+                    .and_then(Thb::from_baht)
+                    .ok_or_else(|| E::custom(MoneyError::Overflow(v.to_string())))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Thb, E> {
+                Thb::from_f64(v).map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Thb, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ThbVisitor)
+    }
+}
+
+impl JsonSchema for Thb {
+    fn schema_name() -> String {
+        "Thb".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some("Amount of THB with at most two decimals".to_owned()),
+                ..Default::default()
+            })),
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some("^-?[0-9]+\\.[0-9]{2}$".to_owned()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thb(s: &str) -> Result<Thb, MoneyError> {
+        s.parse()
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(thb("1000.50"), Ok(Thb::from_satang(100050)));
+        assert_eq!(thb("100,001.00"), Ok(Thb::from_satang(10000100)));
+        assert_eq!(thb(" 1.5 "), Ok(Thb::from_satang(150)));
+        assert_eq!(thb("10"), Ok(Thb::from_satang(1000)));
+        assert_eq!(thb(".25"), Ok(Thb::from_satang(25)));
+        assert_eq!(thb("-3.10"), Ok(Thb::from_satang(-310)));
+        assert_eq!(thb("1.500"), Ok(Thb::from_satang(150)));
+        assert!(matches!(thb("1.005"), Err(MoneyError::SubSatang(_))));
+        assert!(matches!(thb("1e3"), Err(MoneyError::Invalid(_))));
+        assert!(matches!(thb("."), Err(MoneyError::Invalid(_))));
+        assert_eq!(thb(""), Err(MoneyError::Empty));
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(Thb::from_satang(100050).to_string(), "1000.50");
+        assert_eq!(Thb::from_satang(5).to_string(), "0.05");
+        assert_eq!(Thb::from_satang(-310).to_string(), "-3.10");
+    }
+
+    #[test]
+    fn serde_wire_format() {
+        #[derive(Serialize)]
+        struct Body {
+            #[serde(serialize_with = "as_number")]
+            amount: Thb,
+        }
+
+        let amount = Thb::from_satang(100050);
+        assert_eq!(
+            serde_json::to_string(&amount).expect("encoded"),
+            "\"1000.50\""
+        );
+        assert_eq!(
+            serde_json::to_string(&Body {
+                amount: Thb::from_satang(1000)
+            })
+            .expect("encoded"),
+            "{\"amount\":10.00}"
+        );
+        let decoded: Thb = serde_json::from_str("1000.5").expect("number");
+        assert_eq!(decoded, amount);
+        let decoded: Thb = serde_json::from_str("\"1,000.50\"").expect("string");
+        assert_eq!(decoded, amount);
+        assert!(serde_json::from_str::<Thb>("0.001").is_err());
+    }
+
+    #[test]
+    fn serde_value_keeps_decimals() {
+        let amount = Thb::from_satang(10000100);
+        let value = serde_json::to_value(amount).expect("encoded");
+        assert_eq!(value, serde_json::Value::String("100001.00".to_owned()));
+        let decoded: Thb = serde_json::from_value(value).expect("decoded");
+        assert_eq!(decoded, amount);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = Thb::from_satang(150);
+        assert_eq!(a.checked_add(a), Some(Thb::from_satang(300)));
+        assert_eq!(a.checked_sub(a), Some(Thb::ZERO));
+        assert_eq!(a.checked_mul(3), Some(Thb::from_satang(450)));
+        assert_eq!(Thb::from_satang(i64::MAX).checked_add(a), None);
+        assert!(!Thb::ZERO.is_positive());
+    }
+}
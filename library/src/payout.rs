@@ -0,0 +1,976 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use clap::ValueEnum;
+use log::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ApiError, ApiErrorCategory, Error};
+use crate::fields::{FieldError, REF1_MAX_LEN};
+use crate::journal::{JournalEntry, JournalRecord, JournalStore};
+use crate::{Client, QueryOutcome, QueryReq, Ref1, RetryPolicy, Thb, TransferReq, TransferRes};
+
+/// Final answer of [`PayoutOrchestrator::payout`]. In JSON the variant is in
+/// `outcome` field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum PayoutOutcome {
+    /// Money reached the recipient
+    Settled(TransferRes),
+    /// The provider rejected the transfer, nothing was paid. The provider keeps
+    /// the `ref1` and answers -1003 to it, so paying again needs a new `ref1`.
+    Failed {
+        code: ApiError,
+        message: Option<String>,
+    },
+    /// The provider will transfer manually or suspects a duplicate, a person has
+    /// to check it
+    NeedsManualReview {
+        code: ApiError,
+        message: Option<String>,
+    },
+    /// The transfer didn't reach a final state before the deadline. Query `ref1`
+    /// later, don't send it again.
+    Unknown {
+        last_status: Option<ApiError>,
+        message: Option<String>,
+    },
+    /// Amount is above the manual threshold and [`LargeAmountPolicy::Reject`]
+    /// is set, nothing was sent
+    Rejected { amount: Thb, threshold: Thb },
+    /// Amount was split into parts by [`LargeAmountPolicy::Split`]. Parts are
+    /// sent one by one and sending stops at the first part that didn't settle.
+    Split { parts: Vec<PayoutPart> },
+}
+
+impl PayoutOutcome {
+    /// Money reached the recipient, all of it for a split payout
+    pub fn is_settled(&self) -> bool {
+        match self {
+            PayoutOutcome::Settled(_) => true,
+            PayoutOutcome::Split { parts } => parts
+                .iter()
+                .all(|p| p.outcome.as_ref().map_or(false, PayoutOutcome::is_settled)),
+            _ => false,
+        }
+    }
+}
+
+/// One transfer of a split payout
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PayoutPart {
+    /// `ref1` of the payout with suffix "-1", "-2" and so on
+    pub ref1: Ref1,
+    pub amount: Thb,
+    /// `None` if the part wasn't sent
+    pub outcome: Option<PayoutOutcome>,
+    /// Why the part certainly wasn't sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Above this amount the provider doesn't transfer right away, it answers
+/// -2000 and the transfer is made manually later. 100,000.00 THB
+pub const MANUAL_THRESHOLD: Thb = Thb::from_satang(10_000_000);
+
+/// What to do with a payout above the manual threshold
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LargeAmountPolicy {
+    /// Don't send it, the outcome is [`PayoutOutcome::Rejected`]
+    Reject,
+    /// Send several transfers below the threshold, see [`PayoutOutcome::Split`]
+    Split,
+    /// Send it as is, the outcome is [`PayoutOutcome::NeedsManualReview`]
+    #[default]
+    Track,
+}
+
+/// Submits a transfer and polls `/inquery-trans` until the transfer is final or
+/// the deadline of the poll policy passes.
+#[derive(Debug, Clone)]
+pub struct PayoutOrchestrator {
+    client: Client,
+    poll_policy: RetryPolicy,
+    journal: Option<Arc<dyn JournalStore>>,
+    large_amount_policy: LargeAmountPolicy,
+    manual_threshold: Thb,
+    not_found_grace: Duration,
+}
+
+impl PayoutOrchestrator {
+    pub fn new(client: Client) -> Self {
+        PayoutOrchestrator {
+            client,
+            poll_policy: Self::default_poll_policy(),
+            journal: None,
+            large_amount_policy: LargeAmountPolicy::default(),
+            manual_threshold: MANUAL_THRESHOLD,
+            not_found_grace: Duration::from_secs(900),
+        }
+    }
+
+    /// Checks every 2 seconds growing to a minute, for 10 minutes at most
+    pub fn default_poll_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: u32::MAX,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            deadline: Some(Duration::from_secs(600)),
+        }
+    }
+
+    /// How often and how long to query a transfer that is not final yet
+    pub fn poll_policy(mut self, policy: RetryPolicy) -> Self {
+        self.poll_policy = policy;
+        self
+    }
+
+    /// Record every payout in the journal, so it can be recovered after a crash
+    pub fn journal(mut self, journal: Arc<dyn JournalStore>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Default: [`LargeAmountPolicy::Track`]
+    pub fn large_amount_policy(mut self, policy: LargeAmountPolicy) -> Self {
+        self.large_amount_policy = policy;
+        self
+    }
+
+    /// Amounts above it are handled by the large amount policy. Default:
+    /// [`MANUAL_THRESHOLD`]
+    pub fn manual_threshold(mut self, threshold: Thb) -> Self {
+        self.manual_threshold = threshold;
+        self
+    }
+
+    /// How long after its intent a payout the provider doesn't know may still
+    /// show up. Older ones are taken as never sent by [`Self::recover`], which
+    /// needs [`crate::ClientBuilder::not_found_code`]. Default: 15 minutes
+    pub fn not_found_grace(mut self, grace: Duration) -> Self {
+        self.not_found_grace = grace;
+        self
+    }
+
+    /// Errors are returned only when the transfer was certainly not sent, e.g.
+    /// invalid request. Everything after that is described by [`PayoutOutcome`].
+    pub async fn payout(&self, req: TransferReq) -> Result<PayoutOutcome, Error> {
+        if req.amount <= self.manual_threshold {
+            return self.payout_one(req).await;
+        }
+        match self.large_amount_policy {
+            LargeAmountPolicy::Track => self.payout_one(req).await,
+            LargeAmountPolicy::Reject => Ok(PayoutOutcome::Rejected {
+                amount: req.amount,
+                threshold: self.manual_threshold,
+            }),
+            LargeAmountPolicy::Split => self.payout_split(req).await,
+        }
+    }
+
+    async fn payout_split(&self, req: TransferReq) -> Result<PayoutOutcome, Error> {
+        let parts = split(&req, self.manual_threshold)?
+            .into_iter()
+            .map(|part| PayoutPart {
+                ref1: part.ref1,
+                amount: part.amount,
+                outcome: None,
+                error: None,
+            })
+            .collect();
+        self.continue_split(&req, parts).await
+    }
+
+    /// Goes through the parts in order: settled and failed ones are kept, the ones
+    /// that weren't sent are sent and the others are queried. Stops at the first
+    /// part that didn't settle.
+    async fn continue_split(
+        &self,
+        req: &TransferReq,
+        parts: Vec<PayoutPart>,
+    ) -> Result<PayoutOutcome, Error> {
+        let mut rest = parts.into_iter();
+        let mut done: Vec<PayoutPart> = vec![];
+        for mut part in rest.by_ref() {
+            let res = match part.outcome.take() {
+                Some(outcome @ (PayoutOutcome::Settled(_) | PayoutOutcome::Failed { .. })) => {
+                    Ok(outcome)
+                }
+                None => {
+                    self.payout_one(TransferReq {
+                        ref1: part.ref1.clone(),
+                        amount: part.amount,
+                        allow_duplicate: true,
+                        ..req.clone()
+                    })
+                    .await
+                }
+                Some(_) => self.resolve(part.ref1.clone()).await,
+            };
+            (part.outcome, part.error) = match res {
+                Ok(outcome) => (Some(outcome), None),
+                Err(e) if done.is_empty() => return Err(e),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let settled = part
+                .outcome
+                .as_ref()
+                .map_or(false, PayoutOutcome::is_settled);
+            done.push(part);
+            if !settled {
+                break;
+            }
+        }
+        done.extend(rest);
+        Ok(PayoutOutcome::Split { parts: done })
+    }
+
+    /// Continues a payout that an earlier run left with the given outcome, e.g.
+    /// one read from a batch results file. A transfer that may have been made is
+    /// queried instead of being sent again and a failed one stays failed, its
+    /// `ref1` can't be used again. A split payout sends only the parts that
+    /// weren't sent.
+    pub async fn resume(
+        &self,
+        req: TransferReq,
+        previous: PayoutOutcome,
+    ) -> Result<PayoutOutcome, Error> {
+        match previous {
+            PayoutOutcome::Rejected { .. } => self.payout(req).await,
+            PayoutOutcome::Split { parts } => self.continue_split(&req, parts).await,
+            outcome @ (PayoutOutcome::Settled(_) | PayoutOutcome::Failed { .. }) => Ok(outcome),
+            PayoutOutcome::NeedsManualReview { .. } | PayoutOutcome::Unknown { .. } => {
+                self.resolve(req.ref1).await
+            }
+        }
+    }
+
+    async fn payout_one(&self, req: TransferReq) -> Result<PayoutOutcome, Error> {
+        let Some(journal) = &self.journal else {
+            return self.submit(req).await;
+        };
+        let ref1 = req.ref1.clone();
+        journal
+            .append(&JournalRecord::Intent {
+                at: Utc::now(),
+                request: Box::new(req.clone()),
+            })
+            .map_err(Error::Journal)?;
+        let res = self.submit(req).await;
+        let record = match &res {
+            Ok(outcome) => JournalRecord::Outcome {
+                at: Utc::now(),
+                ref1,
+                outcome: outcome.clone(),
+            },
+            Err(e) => JournalRecord::Aborted {
+                at: Utc::now(),
+                ref1,
+                error: e.to_string(),
+            },
+        };
+        // The money has moved already, the entry stays in flight and is resolved
+        // by `recover` later
+        if let Err(e) = journal.append(&record) {
+            error!("Failed to record outcome of {}: {e}", record.ref1());
+        }
+        res
+    }
+
+    /// Queries every payout the journal has in flight, e.g. after a crash, and
+    /// records what was found. Returns the entries with their new outcome, which
+    /// is still `Unknown` for transfers that are not final yet. A payout the
+    /// provider doesn't know after the grace period, e.g. when the process
+    /// stopped before sending it, is recorded as aborted and left out.
+    pub async fn recover(&self) -> Result<Vec<JournalEntry>, Error> {
+        let Some(journal) = &self.journal else {
+            return Ok(vec![]);
+        };
+        let grace = chrono::Duration::from_std(self.not_found_grace)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        let mut recovered = vec![];
+        for mut entry in journal.in_flight().map_err(Error::Journal)? {
+            let ref1 = entry.request.ref1.clone();
+            let found = self
+                .client
+                .query(QueryReq {
+                    ref1: ref1.to_string(),
+                })
+                .await;
+            let stale = Utc::now().signed_duration_since(entry.started_at) > grace;
+            if stale && matches!(found, Ok(QueryOutcome::NotFound { .. })) {
+                warn!("Payout {ref1} never reached the provider, recording it as aborted");
+                journal
+                    .append(&JournalRecord::Aborted {
+                        at: Utc::now(),
+                        ref1,
+                        error: "Not found by the provider after the grace period".to_owned(),
+                    })
+                    .map_err(Error::Journal)?;
+                continue;
+            }
+            let outcome = self.record_found(ref1.clone(), found)?;
+            info!("Recovered payout {ref1}: {outcome:?}");
+            entry.outcome = Some(outcome);
+            recovered.push(entry);
+        }
+        Ok(recovered)
+    }
+
+    /// Queries a transfer that may have been made and records what was found in
+    /// the journal
+    async fn resolve(&self, ref1: Ref1) -> Result<PayoutOutcome, Error> {
+        let query = QueryReq {
+            ref1: ref1.to_string(),
+        };
+        let found = self.client.query(query).await;
+        self.record_found(ref1, found)
+    }
+
+    fn record_found(
+        &self,
+        ref1: Ref1,
+        found: Result<QueryOutcome, Error>,
+    ) -> Result<PayoutOutcome, Error> {
+        let outcome = match found {
+            Ok(outcome) => match classify_query(outcome) {
+                Step::Done(outcome) => outcome,
+                Step::Poll(last) => last.into_unknown(),
+            },
+            Err(e) => PayoutOutcome::Unknown {
+                last_status: e.api_code(),
+                message: Some(e.to_string()),
+            },
+        };
+        if let Some(journal) = &self.journal {
+            journal
+                .append(&JournalRecord::Outcome {
+                    at: Utc::now(),
+                    ref1,
+                    outcome: outcome.clone(),
+                })
+                .map_err(Error::Journal)?;
+        }
+        Ok(outcome)
+    }
+
+    /// Runs [`Self::recover`] and fails with [`Error::InFlight`] if some payouts
+    /// are still not final, so they are not sent again by mistake. Call it
+    /// before new payouts when a journal is attached.
+    pub async fn ensure_recovered(&self) -> Result<Vec<JournalEntry>, Error> {
+        let recovered = self.recover().await?;
+        let in_flight: Vec<Ref1> = recovered
+            .iter()
+            .filter(|e| e.is_in_flight())
+            .map(|e| e.request.ref1.clone())
+            .collect();
+        if in_flight.is_empty() {
+            Ok(recovered)
+        } else {
+            Err(Error::InFlight(in_flight))
+        }
+    }
+
+    async fn submit(&self, req: TransferReq) -> Result<PayoutOutcome, Error> {
+        let query = QueryReq {
+            ref1: req.ref1.to_string(),
+        };
+        let started = Instant::now();
+        let mut last = match self.client.transfer(req).await {
+            Ok(res) => return Ok(PayoutOutcome::Settled(res)),
+            Err(e) => match classify_error(e)? {
+                Step::Done(outcome) => return Ok(outcome),
+                Step::Poll(last) => last,
+            },
+        };
+
+        let mut attempt = 1;
+        loop {
+            attempt += 1;
+            if !self.poll_policy.allows(attempt, started.elapsed()) {
+                return Ok(last.into_unknown());
+            }
+            let delay = self.poll_policy.backoff(attempt);
+            debug!(
+                "Transfer {} is not final: {:?}. Checking it in {delay:?}",
+                query.ref1, last.message
+            );
+            tokio::time::sleep(delay).await;
+
+            let step = match self.client.query(query.clone()).await {
+                Ok(outcome) => classify_query(outcome),
+                Err(e) => Step::Poll(LastSeen {
+                    status: e.api_code(),
+                    message: Some(e.to_string()),
+                }),
+            };
+            last = match step {
+                Step::Done(outcome) => return Ok(outcome),
+                Step::Poll(last) => last,
+            };
+        }
+    }
+}
+
+/// Parts of about the same size, none above the threshold. The amounts differ
+/// by a satang or two, the provider answers 9003 to a similar transfer of the
+/// same amount to the same recipient. The parts are intended repeats, so they
+/// are allowed as duplicates by the local detector. Fails before any part is
+/// made if `ref1` with the longest suffix doesn't fit [`REF1_MAX_LEN`].
+fn split(req: &TransferReq, threshold: Thb) -> Result<Vec<TransferReq>, Error> {
+    let total = req.amount.satang();
+    let max = threshold.satang().max(1);
+    // Part i is a satang less than part i - 1, with the remainder spread over
+    // the first parts
+    let mut count = (total + max - 1) / max;
+    let (base, remainder) = loop {
+        let steps = count * (count - 1) / 2;
+        let (base, remainder) = ((total + steps) / count, (total + steps) % count);
+        if base + i64::from(remainder > 0) <= max {
+            break (base, remainder);
+        }
+        count += 1;
+    };
+    let suffix = format!("-{count}");
+    if req.ref1.as_str().len() + suffix.len() > REF1_MAX_LEN {
+        return Err(Error::Field(FieldError::RefSuffix(
+            req.ref1.to_string(),
+            suffix,
+        )));
+    }
+    (0..count)
+        .map(|i| {
+            let amount = base - i + i64::from(i < remainder);
+            Ok(TransferReq {
+                ref1: format!("{}-{}", req.ref1, i + 1)
+                    .parse()
+                    .map_err(Error::Field)?,
+                amount: Thb::from_satang(amount),
+                allow_duplicate: true,
+                ..req.clone()
+            })
+        })
+        .collect()
+}
+
+impl Client {
+    /// Transfer and wait until it is settled or failed, see [`PayoutOrchestrator`]
+    pub async fn payout(&self, req: TransferReq) -> Result<PayoutOutcome, Error> {
+        PayoutOrchestrator::new(self.clone()).payout(req).await
+    }
+}
+
+/// What was known about the transfer when polling stopped
+struct LastSeen {
+    status: Option<ApiError>,
+    message: Option<String>,
+}
+
+impl LastSeen {
+    fn into_unknown(self) -> PayoutOutcome {
+        PayoutOutcome::Unknown {
+            last_status: self.status,
+            message: self.message,
+        }
+    }
+}
+
+enum Step {
+    Done(PayoutOutcome),
+    Poll(LastSeen),
+}
+
+fn classify_code(code: ApiError, message: Option<String>) -> Step {
+    match code.category() {
+        ApiErrorCategory::ManualReview => {
+            Step::Done(PayoutOutcome::NeedsManualReview { code, message })
+        }
+        // The same ref1 was already sent, its status is found by the query
+        _ if code == ApiError::DuplicateTransaction => Step::Poll(LastSeen {
+            status: Some(code),
+            message,
+        }),
+        ApiErrorCategory::DuplicateSuspected => {
+            Step::Done(PayoutOutcome::NeedsManualReview { code, message })
+        }
+        _ if code.is_final() => Step::Done(PayoutOutcome::Failed { code, message }),
+        _ => Step::Poll(LastSeen {
+            status: Some(code),
+            message,
+        }),
+    }
+}
+
+/// Errors of the transfer call. Only errors that certainly weren't paid are
+/// returned, for the rest the request may have reached the provider.
+fn classify_error(err: Error) -> Result<Step, Error> {
+    match err {
+        Error::Api(code, info) => Ok(classify_code(code, info.message)),
+        err if err.is_unpaid() => Err(err),
+        err => Ok(Step::Poll(LastSeen {
+            status: err.api_code(),
+            message: Some(err.to_string()),
+        })),
+    }
+}
+
+fn classify_query(outcome: QueryOutcome) -> Step {
+    match outcome {
+        QueryOutcome::Success(res) => Step::Done(PayoutOutcome::Settled(res.into())),
+        QueryOutcome::Pending { message, .. } => Step::Poll(LastSeen {
+            status: Some(ApiError::WaitingBankResponse),
+            message: Some(message),
+        }),
+        QueryOutcome::Failed { code, message, .. }
+        | QueryOutcome::NeedsReview { code, message, .. } => classify_code(code, Some(message)),
+        // Right after sending, the provider may not have recorded the transfer yet
+        QueryOutcome::NotFound { code, message } => Step::Poll(LastSeen {
+            status: Some(code),
+            message: Some(message),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::BankAccount;
+    use crate::journal::MemoryJournal;
+    use crate::money::Thb;
+    use crate::query::QueryResError;
+    use crate::transport::{MockTransport, TransportError};
+    use crate::{Bank, PAYOUT_PATH, QUERY_PATH};
+    use serde_json::json;
+
+    fn orchestrator(mock: Arc<MockTransport>) -> PayoutOrchestrator {
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock)
+            .build()
+            .expect("client");
+        PayoutOrchestrator::new(client).poll_policy(RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            multiplier: 2.0,
+            jitter: 0.0,
+            deadline: None,
+        })
+    }
+
+    fn request() -> TransferReq {
+        TransferReq {
+            bankacc: BankAccount::new(Bank::Kasikorn, "0652078409").expect("account"),
+            bank: Bank::Kasikorn,
+            accname: "Manop Tangngam".to_owned(),
+            amount: Thb::from_satang(100),
+            mobileno: "0805933181".parse().expect("mobile"),
+            transaction_by: "Jack Developer".to_owned(),
+            ref1: "202205170841".parse().expect("ref1"),
+            ref2: None,
+            ref3: None,
+            ref4: None,
+            line_token: None,
+            email: None,
+            allow_duplicate: false,
+        }
+    }
+
+    fn query_success() -> serde_json::Value {
+        json!({
+            "status": "1000",
+            "message": "Success",
+            "accname": "MANOP DEVELOPER",
+            "bankacc": "0652078409",
+            "bankcode": "004",
+            "amount": "1.00",
+            "ref1": "202205170841",
+            "created_date": "2022-05-17 08:41:48.320",
+            "transfer_date": "2022-05-17 08:41:50.447",
+            "transfer_transactionId": "2022051790WiXyi9Lwu0iuHgT"
+        })
+    }
+
+    fn paths(mock: &MockTransport) -> Vec<String> {
+        mock.requests().into_iter().map(|r| r.path).collect()
+    }
+
+    #[tokio::test]
+    async fn pending_transfer_settles() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 9090, "message": "Waiting"}));
+        mock.push_query(json!({"status": "9090", "message": "Waiting"}));
+        mock.push_query(query_success());
+        let outcome = orchestrator(mock.clone())
+            .payout(request())
+            .await
+            .expect("payout");
+        assert!(outcome.is_settled(), "{outcome:?}");
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, QUERY_PATH, QUERY_PATH]);
+    }
+
+    #[tokio::test]
+    async fn lost_response_is_resolved_by_query() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_response(
+            PAYOUT_PATH,
+            Err(TransportError::NoResponse("timed out".to_owned())),
+        );
+        mock.push_query(query_success());
+        let outcome = orchestrator(mock.clone())
+            .payout(request())
+            .await
+            .expect("payout");
+        assert!(outcome.is_settled(), "{outcome:?}");
+    }
+
+    #[tokio::test]
+    async fn undelivered_transfer_is_error() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_response(
+            PAYOUT_PATH,
+            Err(TransportError::NotSent("connection refused".to_owned())),
+        );
+        let res = orchestrator(mock.clone()).payout(request()).await;
+        assert!(matches!(res, Err(Error::NotDelivered(_))), "{res:?}");
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH]);
+    }
+
+    #[tokio::test]
+    async fn final_outcomes() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": -2000, "message": "Manual"}));
+        let outcome = orchestrator(mock.clone()).payout(request()).await;
+        assert!(matches!(
+            outcome,
+            Ok(PayoutOutcome::NeedsManualReview {
+                code: ApiError::ManualTransfer,
+                ..
+            })
+        ));
+
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        let outcome = orchestrator(mock.clone()).payout(request()).await;
+        assert!(matches!(
+            outcome,
+            Ok(PayoutOutcome::Failed {
+                code: ApiError::InsufficientBalance,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn not_found_after_lost_response_is_unknown() {
+        let mock = Arc::new(MockTransport::new());
+        let journal = Arc::new(MemoryJournal::new());
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock.clone())
+            .not_found_code(ApiError::from_code(-1010))
+            .build()
+            .expect("client");
+        let orchestrator = PayoutOrchestrator::new(client)
+            .poll_policy(orchestrator(mock.clone()).poll_policy)
+            .journal(journal.clone());
+        mock.push_response(
+            PAYOUT_PATH,
+            Err(TransportError::NoResponse("timed out".to_owned())),
+        );
+        for _ in 0..3 {
+            mock.push_query(json!({"status": "-1010", "message": "Not found"}));
+        }
+        let outcome = orchestrator.payout(request()).await;
+        assert!(
+            matches!(
+                outcome,
+                Ok(PayoutOutcome::Unknown {
+                    last_status: Some(ApiError::Unknown(-1010)),
+                    ..
+                })
+            ),
+            "{outcome:?}"
+        );
+        assert_eq!(journal.in_flight().expect("in flight").len(), 1);
+
+        let after_send = Error::ConvertQuery(QueryResError::MissingField("ref1"));
+        assert!(matches!(classify_error(after_send), Ok(Step::Poll(_))));
+        let before_send = Error::NotPositiveAmount(Thb::ZERO);
+        assert!(classify_error(before_send).is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_after_deadline() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 9090, "message": "Waiting"}));
+        for _ in 0..5 {
+            mock.push_query(json!({"status": "9090", "message": "Waiting"}));
+        }
+        let outcome = orchestrator(mock.clone()).payout(request()).await;
+        assert!(matches!(
+            outcome,
+            Ok(PayoutOutcome::Unknown {
+                last_status: Some(ApiError::WaitingBankResponse),
+                ..
+            })
+        ));
+        assert_eq!(paths(&mock).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn journal_records_and_recovers() {
+        let mock = Arc::new(MockTransport::new());
+        let journal = Arc::new(MemoryJournal::new());
+        // Process crashed after writing the intent
+        journal
+            .append(&JournalRecord::Intent {
+                at: Utc::now(),
+                request: Box::new(request()),
+            })
+            .expect("appended");
+        mock.push_query(query_success());
+        let orchestrator = orchestrator(mock.clone()).journal(journal.clone());
+        let recovered = orchestrator.recover().await.expect("recovered");
+        assert_eq!(recovered.len(), 1);
+        assert!(recovered[0]
+            .outcome
+            .as_ref()
+            .map_or(false, |o| o.is_settled()));
+        assert!(journal.in_flight().expect("in flight").is_empty());
+
+        let mut invalid = request();
+        invalid.ref1 = "invalid".parse().expect("ref1");
+        invalid.amount = Thb::ZERO;
+        let res = orchestrator.payout(invalid).await;
+        assert!(matches!(res, Err(Error::NotPositiveAmount(_))));
+        assert_eq!(journal.records().expect("records").len(), 4);
+        assert!(journal.in_flight().expect("in flight").is_empty());
+    }
+
+    #[tokio::test]
+    async fn crash_before_send_is_aborted_after_grace() {
+        let mock = Arc::new(MockTransport::new());
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock.clone())
+            .not_found_code(ApiError::from_code(-1010))
+            .build()
+            .expect("client");
+        let journal = Arc::new(MemoryJournal::new());
+        let intent = |at| JournalRecord::Intent {
+            at,
+            request: Box::new(request()),
+        };
+        // The process stopped after the intent, before the transfer was sent
+        journal
+            .append(&intent(Utc::now() - chrono::Duration::hours(1)))
+            .expect("appended");
+        let orchestrator = PayoutOrchestrator::new(client).journal(journal.clone());
+        mock.push_query(json!({"status": -1010, "message": "Transaction not found"}));
+        let recovered = orchestrator.ensure_recovered().await.expect("recovered");
+        assert!(recovered.is_empty());
+        assert!(journal.in_flight().expect("in flight").is_empty());
+        mock.push_transfer(json!({
+            "status": 1000,
+            "message": "Success",
+            "transaction_id": "2022030288DtbRwK0IKr536t4",
+            "transactionDate_time": "2023-09-20T17:35:13",
+        }));
+        let outcome = orchestrator.payout(request()).await.expect("payout");
+        assert!(outcome.is_settled());
+
+        // A recent intent may still show up, it stays in flight
+        journal.append(&intent(Utc::now())).expect("appended");
+        mock.push_query(json!({"status": -1010, "message": "Transaction not found"}));
+        let res = orchestrator.ensure_recovered().await;
+        assert!(matches!(res, Err(Error::InFlight(_))), "{res:?}");
+        assert_eq!(paths(&mock), vec![QUERY_PATH, PAYOUT_PATH, QUERY_PATH]);
+    }
+
+    #[tokio::test]
+    async fn in_flight_payouts_block_new_ones() {
+        let mock = Arc::new(MockTransport::new());
+        let journal = Arc::new(MemoryJournal::new());
+        journal
+            .append(&JournalRecord::Intent {
+                at: Utc::now(),
+                request: Box::new(request()),
+            })
+            .expect("appended");
+        let orchestrator = orchestrator(mock.clone()).journal(journal.clone());
+        mock.push_query(json!({"status": "9090", "message": "Pending"}));
+        let res = orchestrator.ensure_recovered().await;
+        assert!(
+            matches!(&res, Err(Error::InFlight(refs)) if refs == &[request().ref1]),
+            "{res:?}"
+        );
+        assert_eq!(paths(&mock), vec![QUERY_PATH]);
+
+        mock.push_query(query_success());
+        let recovered = orchestrator.ensure_recovered().await.expect("recovered");
+        assert_eq!(recovered.len(), 1);
+        assert!(orchestrator
+            .ensure_recovered()
+            .await
+            .expect("nothing to recover")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn large_amount_policies() {
+        let mock = Arc::new(MockTransport::new());
+        let mut req = request();
+        req.amount = Thb::from_satang(25_000_000);
+
+        let outcome = orchestrator(mock.clone())
+            .large_amount_policy(LargeAmountPolicy::Reject)
+            .payout(req.clone())
+            .await;
+        assert!(matches!(outcome, Ok(PayoutOutcome::Rejected { .. })));
+        assert!(mock.requests().is_empty());
+
+        mock.push_transfer(json!({"status": -2000, "message": "Manual"}));
+        let outcome = orchestrator(mock.clone()).payout(req.clone()).await;
+        assert!(matches!(
+            outcome,
+            Ok(PayoutOutcome::NeedsManualReview { .. })
+        ));
+
+        mock.push_query(query_success());
+        mock.push_transfer(json!({"status": 9090, "message": "Waiting"}));
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        let outcome = orchestrator(mock.clone())
+            .large_amount_policy(LargeAmountPolicy::Split)
+            .payout(req)
+            .await
+            .expect("payout");
+        let PayoutOutcome::Split { parts } = &outcome else {
+            panic!("split expected: {outcome:?}");
+        };
+        let parts: Vec<(&str, i64, bool, bool)> = parts
+            .iter()
+            .map(|p| {
+                (
+                    p.ref1.as_str(),
+                    p.amount.satang(),
+                    p.outcome.is_some(),
+                    p.outcome.as_ref().map_or(false, PayoutOutcome::is_settled),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                ("202205170841-1", 8_333_335, true, true),
+                ("202205170841-2", 8_333_333, true, false),
+                ("202205170841-3", 8_333_332, false, false),
+            ]
+        );
+        assert!(!outcome.is_settled());
+
+        // The provider keeps the ref1 of the failed part, nothing is sent again
+        let sent = paths(&mock).len();
+        let resumed = orchestrator(mock.clone())
+            .resume(request_of(25_000_000), outcome.clone())
+            .await
+            .expect("resumed");
+        assert_eq!(resumed, outcome);
+        assert_eq!(paths(&mock).len(), sent);
+    }
+
+    #[test]
+    fn split_amounts_differ() {
+        let threshold = Thb::from_satang(10_000_000);
+        for total in [10_000_001, 20_000_000, 25_000_000, 30_000_000, 99_999_999] {
+            let parts = split(&request_of(total), threshold).expect("split");
+            let mut amounts: Vec<i64> = parts.iter().map(|p| p.amount.satang()).collect();
+            assert_eq!(amounts.iter().sum::<i64>(), total);
+            assert!(amounts.iter().all(|a| *a > 0 && *a <= threshold.satang()));
+            amounts.dedup();
+            assert_eq!(amounts.len(), parts.len(), "{total}");
+        }
+    }
+
+    #[tokio::test]
+    async fn split_ref1_must_fit_suffix() {
+        let mock = Arc::new(MockTransport::new());
+        let orchestrator = orchestrator(mock.clone()).large_amount_policy(LargeAmountPolicy::Split);
+        let long = |len: usize| TransferReq {
+            ref1: "r".repeat(len).parse().expect("ref1"),
+            ..request_of(25_000_000)
+        };
+        let res = orchestrator.payout(long(REF1_MAX_LEN - 1)).await;
+        assert!(
+            matches!(&res, Err(Error::Field(FieldError::RefSuffix(_, suffix))) if suffix == "-3"),
+            "{res:?}"
+        );
+        assert!(mock.requests().is_empty());
+
+        mock.push_transfer(transfer_success());
+        mock.push_transfer(transfer_success());
+        mock.push_transfer(transfer_success());
+        let outcome = orchestrator
+            .payout(long(REF1_MAX_LEN - 2))
+            .await
+            .expect("payout");
+        let PayoutOutcome::Split { parts } = &outcome else {
+            panic!("split expected: {outcome:?}");
+        };
+        assert_eq!(parts[2].ref1.as_str().len(), REF1_MAX_LEN);
+        assert!(outcome.is_settled());
+    }
+
+    fn request_of(satang: i64) -> TransferReq {
+        TransferReq {
+            amount: Thb::from_satang(satang),
+            ..request()
+        }
+    }
+
+    fn transfer_success() -> serde_json::Value {
+        json!({
+            "status": 1000,
+            "message": "Success",
+            "transaction_id": "2022030288DtbRwK0IKr536t4",
+            "transactionDate_time": "2023-09-20T17:35:13",
+        })
+    }
+
+    #[tokio::test]
+    async fn resume_queries_unclear_transfers() {
+        let mock = Arc::new(MockTransport::new());
+        let unknown = PayoutOutcome::Unknown {
+            last_status: None,
+            message: None,
+        };
+        mock.push_query(query_success());
+        let outcome = orchestrator(mock.clone())
+            .resume(request(), unknown.clone())
+            .await
+            .expect("resumed");
+        assert!(outcome.is_settled());
+        assert_eq!(paths(&mock), vec![QUERY_PATH]);
+
+        let part = |i: usize, outcome: Option<PayoutOutcome>| PayoutPart {
+            ref1: format!("202205170841-{i}").parse().expect("ref1"),
+            amount: Thb::from_satang(50),
+            outcome,
+            error: None,
+        };
+        let previous = PayoutOutcome::Split {
+            parts: vec![part(1, Some(unknown)), part(2, None)],
+        };
+        mock.push_query(query_success());
+        mock.push_transfer(transfer_success());
+        let outcome = orchestrator(mock.clone())
+            .resume(request(), previous)
+            .await
+            .expect("resumed");
+        assert!(outcome.is_settled(), "{outcome:?}");
+        assert_eq!(paths(&mock), vec![QUERY_PATH, QUERY_PATH, PAYOUT_PATH]);
+    }
+}
@@ -1,38 +1,108 @@
-use chrono::NaiveDateTime;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use thiserror::Error;
 
-use crate::{error::ApiError, Bank};
+use crate::{
+    de,
+    error::{ApiError, ApiErrorCategory},
+    money::{MoneyError, Thb},
+    timestamp::{self, BangkokTime},
+    Bank, TransferRes,
+};
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct QueryReq {
     /// External ID of withdraw request
     pub ref1: String,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Transaction that was transferred successfully
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct QueryRes {
-    status: ApiError,
-    accname: String,
-    bankacc: String,
-    bank: Bank,
-    amount: f64,
-    ref1: String,
-    ref2: Option<String>,
-    ref3: Option<String>,
-    ref4: Option<String>,
-    created_date: NaiveDateTime,
-    transfer_date: NaiveDateTime,
-    transfer_transaction_id: String,
+    pub status: ApiError,
+    /// Name of the recipient as the bank knows it
+    pub accname: String,
+    pub bankacc: String,
+    pub bank: Bank,
+    pub amount: Thb,
+    pub ref1: String,
+    pub ref2: Option<String>,
+    pub ref3: Option<String>,
+    pub ref4: Option<String>,
+    pub created_date: BangkokTime,
+    pub transfer_date: BangkokTime,
+    pub transfer_transaction_id: String,
+    /// Fields of the response this library doesn't know about
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// Whatever the provider knows about the transaction. Failed transfers usually
+/// have everything except the transfer date and id.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct QueryRecord {
+    pub accname: Option<String>,
+    pub bankacc: Option<String>,
+    pub bank: Option<Bank>,
+    pub amount: Option<Thb>,
+    pub ref1: Option<String>,
+    pub ref2: Option<String>,
+    pub ref3: Option<String>,
+    pub ref4: Option<String>,
+    pub created_date: Option<BangkokTime>,
+    pub transfer_date: Option<BangkokTime>,
+    pub transfer_transaction_id: Option<String>,
+    /// Fields of the response this library doesn't know about
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Value>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+/// State of the transaction found by `ref1`. In JSON the variant is in `outcome`
+/// field, e.g. `{"outcome": "pending", "message": "...", "record": {...}}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum QueryOutcome {
+    /// Money was transferred
+    Success(QueryRes),
+    /// The bank hasn't confirmed the transfer yet, check again later
+    Pending {
+        message: String,
+        record: QueryRecord,
+    },
+    /// The transfer was rejected, `code` and `message` tell why
+    Failed {
+        code: ApiError,
+        message: String,
+        record: QueryRecord,
+    },
+    /// Manual transfer (-2000) or suspected duplicate, the provider may still pay.
+    /// A person has to check it.
+    NeedsReview {
+        code: ApiError,
+        message: String,
+        record: QueryRecord,
+    },
+    /// The provider has no transaction with the `ref1`. Only the code set by
+    /// [`crate::ClientBuilder::not_found_code`] means that, the provider doesn't
+    /// document one.
+    NotFound { code: ApiError, message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct QueryResInner {
-    status: String,
+    #[serde(deserialize_with = "de::status")]
+    status: i32,
     message: String,
     accname: Option<String>,
     bankacc: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
     bankcode: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
     amount: Option<String>,
     ref1: Option<String>,
     ref2: Option<String>,
@@ -42,6 +112,8 @@ pub struct QueryResInner {
     transfer_date: Option<String>,
     #[serde(rename = "transfer_transactionId")]
     transfer_transaction_id: Option<String>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
 }
 
 #[derive(Debug, Clone, PartialEq, Error)]
@@ -54,23 +126,200 @@ pub enum QueryResError {
     BankcodeIsNotInt(String),
     #[error("We don't know bank with code: {0}")]
     UnknownBank(u32),
-    #[error("Amount THB is not in float format: {0}")]
-    AmountIsNotFloat(String),
+    #[error("Amount THB is not in decimal format: {0}")]
+    AmountFormat(MoneyError),
     #[error("Failed to parse timestamp: {0}. Error: {1}")]
     TimestampParse(String, String),
-    #[error("Failed to parse status as int: {0}")]
-    StatusIsNotInt(String),
+}
+
+/// Successful query describes the same transfer that `/payout` would have returned
+impl From<QueryRes> for TransferRes {
+    fn from(value: QueryRes) -> Self {
+        TransferRes {
+            payout_ref: None,
+            transaction_id: value.transfer_transaction_id,
+            transaction_date_time: value.transfer_date,
+            qrstring: None,
+            extra: value.extra,
+        }
+    }
+}
+
+impl QueryRes {
+    pub fn created_date_utc(&self) -> DateTime<Utc> {
+        timestamp::to_utc(&self.created_date)
+    }
+
+    pub fn transfer_date_utc(&self) -> DateTime<Utc> {
+        timestamp::to_utc(&self.transfer_date)
+    }
+}
+
+impl QueryRecord {
+    /// True when the response carried no transaction fields at all
+    pub fn is_empty(&self) -> bool {
+        *self == QueryRecord::default()
+    }
+}
+
+impl QueryOutcome {
+    pub fn status(&self) -> ApiError {
+        match self {
+            QueryOutcome::Success(res) => res.status,
+            QueryOutcome::Pending { .. } => ApiError::WaitingBankResponse,
+            QueryOutcome::Failed { code, .. }
+            | QueryOutcome::NeedsReview { code, .. }
+            | QueryOutcome::NotFound { code, .. } => *code,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, QueryOutcome::Success(_))
+    }
+
+    /// The transaction won't change anymore. Pending transfer may still complete
+    /// and failure that needs manual review may be resolved later.
+    pub fn is_final(&self) -> bool {
+        match self {
+            QueryOutcome::Success(_) => true,
+            QueryOutcome::Pending { .. } | QueryOutcome::NotFound { .. } => false,
+            QueryOutcome::Failed { code, .. } | QueryOutcome::NeedsReview { code, .. } => {
+                code.is_final()
+            }
+        }
+    }
+
+    /// Fields of the pending or failed transaction
+    pub fn record(&self) -> Option<&QueryRecord> {
+        match self {
+            QueryOutcome::Pending { record, .. }
+            | QueryOutcome::Failed { record, .. }
+            | QueryOutcome::NeedsReview { record, .. } => Some(record),
+            QueryOutcome::Success(_) | QueryOutcome::NotFound { .. } => None,
+        }
+    }
+}
+
+impl QueryResInner {
+    /// Provider's explanation of the status
+    pub(crate) fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn status(&self) -> ApiError {
+        ApiError::from_code(self.status)
+    }
+}
+
+fn parse_bank(bankcode: String) -> Result<Bank, QueryResError> {
+    let bank_code = bankcode
+        .parse::<u32>()
+        .map_err(|_| QueryResError::BankcodeIsNotInt(bankcode))?;
+    Bank::from_code(bank_code).ok_or(QueryResError::UnknownBank(bank_code))
+}
+
+fn parse_date(date: String) -> Result<BangkokTime, QueryResError> {
+    timestamp::parse(&date).map_err(|e| QueryResError::TimestampParse(date, e))
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+impl TryFrom<QueryResInner> for QueryRecord {
+    type Error = QueryResError;
+
+    fn try_from(value: QueryResInner) -> Result<Self, Self::Error> {
+        Ok(QueryRecord {
+            accname: value.accname.and_then(non_empty),
+            bankacc: value.bankacc.and_then(non_empty),
+            bank: value
+                .bankcode
+                .and_then(non_empty)
+                .map(parse_bank)
+                .transpose()?,
+            amount: value
+                .amount
+                .and_then(non_empty)
+                .map(|a| a.parse().map_err(QueryResError::AmountFormat))
+                .transpose()?,
+            ref1: value.ref1.and_then(non_empty),
+            ref2: value.ref2.and_then(non_empty),
+            ref3: value.ref3.and_then(non_empty),
+            ref4: value.ref4.and_then(non_empty),
+            created_date: value
+                .created_date
+                .and_then(non_empty)
+                .map(parse_date)
+                .transpose()?,
+            transfer_date: value
+                .transfer_date
+                .and_then(non_empty)
+                .map(parse_date)
+                .transpose()?,
+            transfer_transaction_id: value.transfer_transaction_id.and_then(non_empty),
+            extra: value.extra,
+        })
+    }
+}
+
+impl QueryOutcome {
+    /// Only malformed bodies, undocumented codes and statuses that say nothing
+    /// about the transaction (service is down, bad credentials, bad request) are
+    /// errors. `not_found` is the code the provider answers for unknown `ref1`.
+    pub(crate) fn from_response(
+        value: QueryResInner,
+        not_found: Option<ApiError>,
+    ) -> Result<Self, QueryResError> {
+        let status = value.status();
+        if status.is_success() {
+            return QueryRes::try_from(value).map(QueryOutcome::Success);
+        }
+        let message = value.message.clone();
+        let record = QueryRecord::try_from(value)?;
+        if status.is_pending() {
+            return Ok(QueryOutcome::Pending { message, record });
+        }
+        if not_found == Some(status) {
+            return Ok(QueryOutcome::NotFound {
+                code: status,
+                message,
+            });
+        }
+        match status.category() {
+            ApiErrorCategory::ManualReview | ApiErrorCategory::DuplicateSuspected => {
+                Ok(QueryOutcome::NeedsReview {
+                    code: status,
+                    message,
+                    record,
+                })
+            }
+            ApiErrorCategory::Unknown => Err(QueryResError::ApiError(status)),
+            ApiErrorCategory::Retryable
+            | ApiErrorCategory::AuthFailure
+            | ApiErrorCategory::InvalidRequest
+                if record.is_empty() =>
+            {
+                Err(QueryResError::ApiError(status))
+            }
+            _ => Ok(QueryOutcome::Failed {
+                code: status,
+                message,
+                record,
+            }),
+        }
+    }
 }
 
 impl TryFrom<QueryResInner> for QueryRes {
     type Error = QueryResError;
 
     fn try_from(value: QueryResInner) -> Result<Self, Self::Error> {
-        let istatus = value
-            .status
-            .parse()
-            .map_err(|_| QueryResError::StatusIsNotInt(value.status))?;
-        let status = ApiError::from_code(istatus);
+        let status = value.status();
         if !status.is_success() {
             return Err(QueryResError::ApiError(status));
         }
@@ -85,9 +334,6 @@ impl TryFrom<QueryResInner> for QueryRes {
             .bankacc
             .ok_or(QueryResError::MissingField("bankacc"))?;
         let ref1 = value.ref1.ok_or(QueryResError::MissingField("ref1"))?;
-        let ref2 = value.ref2.ok_or(QueryResError::MissingField("ref2"))?;
-        let ref3 = value.ref3.ok_or(QueryResError::MissingField("ref3"))?;
-        let ref4 = value.ref4.ok_or(QueryResError::MissingField("ref4"))?;
         let amount = value.amount.ok_or(QueryResError::MissingField("amount"))?;
         let created_date = value
             .created_date
@@ -99,28 +345,20 @@ impl TryFrom<QueryResInner> for QueryRes {
             .transfer_transaction_id
             .ok_or(QueryResError::MissingField("transfer_transaction_id"))?;
 
-        let bank_code = bankcode
-            .parse::<u32>()
-            .map_err(|_| QueryResError::BankcodeIsNotInt(bankcode))?;
         Ok(QueryRes {
             status,
             accname,
             bankacc,
-            bank: Bank::from_code(bank_code).ok_or(QueryResError::UnknownBank(bank_code))?,
-            amount: amount
-                .trim()
-                .replace(',', "")
-                .parse()
-                .map_err(|_| QueryResError::AmountIsNotFloat(amount))?,
-            ref1: ref1,
-            ref2: if ref2.is_empty() { None } else { Some(ref2) },
-            ref3: if ref3.is_empty() { None } else { Some(ref3) },
-            ref4: if ref4.is_empty() { None } else { Some(ref4) },
-            created_date: NaiveDateTime::parse_from_str(&created_date, "%Y-%m-%d %H:%M:%S%.f")
-                .map_err(|e| QueryResError::TimestampParse(created_date, e.to_string()))?,
-            transfer_date: NaiveDateTime::parse_from_str(&transfer_date, "%Y-%m-%d %H:%M:%S%.f")
-                .map_err(|e| QueryResError::TimestampParse(transfer_date, e.to_string()))?,
+            bank: parse_bank(bankcode)?,
+            amount: amount.parse().map_err(QueryResError::AmountFormat)?,
+            ref1,
+            ref2: value.ref2.and_then(non_empty),
+            ref3: value.ref3.and_then(non_empty),
+            ref4: value.ref4.and_then(non_empty),
+            created_date: parse_date(created_date)?,
+            transfer_date: parse_date(transfer_date)?,
             transfer_transaction_id,
+            extra: value.extra,
         })
     }
 }
@@ -128,6 +366,11 @@ impl TryFrom<QueryResInner> for QueryRes {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::NaiveDateTime;
+
+    fn local(millis: i64) -> BangkokTime {
+        timestamp::from_local(NaiveDateTime::from_timestamp_millis(millis).expect("timestamp"))
+    }
 
     #[test]
     fn query_response_success() {
@@ -151,16 +394,17 @@ mod tests {
             bankacc: "6652078409".to_owned(),
             bank: Bank::Kasikorn,
             accname: "MANOP DEVELOPER".to_owned(),
-            amount: 1.0,
+            amount: Thb::from_satang(100),
             ref1: "202205170841".to_owned(),
             ref2: Some("KASiKORN BANK".to_owned()),
             ref3: None,
             ref4: None,
-            created_date: NaiveDateTime::from_timestamp_millis(1652776908320).expect("timestamp"),
-            transfer_date: NaiveDateTime::from_timestamp_millis(1652776910447).expect("timestamp"),
+            created_date: local(1652776908320),
+            transfer_date: local(1652776910447),
             transfer_transaction_id: "2022051790WiXyi9Lwu0iuHgT".to_owned(),
+            extra: BTreeMap::new(),
         };
-        let example_inner: QueryResInner = serde_json::from_str(&example).expect("parsed");
+        let example_inner: QueryResInner = serde_json::from_str(example).expect("parsed");
         let example_pretty: QueryRes = example_inner.try_into().expect("converted");
         assert_eq!(example_pretty, datum);
     }
@@ -180,11 +424,147 @@ mod tests {
             \"ref4\": \"\",
             \"created_date\": \"2022-05-17 06:47:58.860\"
             }";
-        let example_inner: QueryResInner = serde_json::from_str(&example).expect("parsed");
-        let example_pretty: Result<QueryRes, QueryResError> = example_inner.try_into();
+        let example_inner: QueryResInner = serde_json::from_str(example).expect("parsed");
+        let example_strict: Result<QueryRes, QueryResError> = example_inner.clone().try_into();
         assert_eq!(
-            example_pretty,
+            example_strict,
             Err(QueryResError::ApiError(ApiError::from_code(5009)))
         );
+        let outcome = QueryOutcome::from_response(example_inner, None).expect("converted");
+        assert_eq!(outcome.status(), ApiError::IncorrectAccount);
+        assert!(outcome.is_final());
+        let record = QueryRecord {
+            accname: Some("MANOP DEVELOPER".to_owned()),
+            bankacc: Some("66520784099".to_owned()),
+            bank: Some(Bank::Kasikorn),
+            amount: Some(Thb::from_satang(10000100)),
+            ref1: Some("202205170648".to_owned()),
+            ref2: Some("KASiKORN BANK".to_owned()),
+            created_date: Some(local(1652770078860)),
+            ..QueryRecord::default()
+        };
+        assert_eq!(
+            outcome,
+            QueryOutcome::Failed {
+                code: ApiError::IncorrectAccount,
+                message: "Incorrect 'Account To' number. Please try again".to_owned(),
+                record,
+            }
+        );
+    }
+
+    #[test]
+    fn query_response_without_transaction() {
+        let not_found = ApiError::from_code(-1010);
+        let outcome = |body: &str| {
+            let inner: QueryResInner = serde_json::from_str(body).expect("parsed");
+            QueryOutcome::from_response(inner, Some(not_found))
+        };
+        assert_eq!(
+            outcome("{\"status\": -1010, \"message\": \"Not found\"}"),
+            Ok(QueryOutcome::NotFound {
+                code: not_found,
+                message: "Not found".to_owned(),
+            })
+        );
+        assert_eq!(
+            outcome("{\"status\": -2000, \"message\": \"Manual\"}"),
+            Ok(QueryOutcome::NeedsReview {
+                code: ApiError::ManualTransfer,
+                message: "Manual".to_owned(),
+                record: QueryRecord::default(),
+            })
+        );
+        assert_eq!(
+            outcome("{\"status\": -1009, \"message\": \"Balance\"}"),
+            Ok(QueryOutcome::Failed {
+                code: ApiError::InsufficientBalance,
+                message: "Balance".to_owned(),
+                record: QueryRecord::default(),
+            })
+        );
+        assert_eq!(
+            outcome("{\"status\": -3001, \"message\": \"Who knows\"}"),
+            Err(QueryResError::ApiError(ApiError::from_code(-3001)))
+        );
+        let inner: QueryResInner =
+            serde_json::from_str("{\"status\": -1010, \"message\": \"Not found\"}")
+                .expect("parsed");
+        assert_eq!(
+            QueryOutcome::from_response(inner, None),
+            Err(QueryResError::ApiError(not_found))
+        );
+        assert_eq!(
+            outcome("{\"status\": \"9090\", \"message\": \"Waiting\"}"),
+            Ok(QueryOutcome::Pending {
+                message: "Waiting".to_owned(),
+                record: QueryRecord::default(),
+            })
+        );
+        assert_eq!(
+            outcome("{\"status\": \"9001\", \"message\": \"Unavailable\"}"),
+            Err(QueryResError::ApiError(ApiError::ServiceUnavailable))
+        );
+    }
+
+    #[test]
+    fn query_response_lenient() {
+        let example = "{
+            \"status\": 1000,
+            \"message\": \"Success\",
+            \"accname\": \"MANOP DEVELOPER\",
+            \"bankacc\": \"6652078409\",
+            \"bankcode\": 4,
+            \"amount\": 1.5,
+            \"ref1\": \"202205170841\",
+            \"created_date\": \"2022-05-17T08:41:48\",
+            \"transfer_date\": \"2022-05-17 08:41:50.447\",
+            \"transfer_transactionId\": \"2022051790WiXyi9Lwu0iuHgT\",
+            \"channel\": \"WEB\"
+            }";
+        let example_inner: QueryResInner = serde_json::from_str(example).expect("parsed");
+        let res: QueryRes = example_inner.try_into().expect("converted");
+        assert_eq!(res.bank, Bank::Kasikorn);
+        assert_eq!(res.amount, Thb::from_satang(150));
+        assert_eq!(res.ref2, None);
+        assert_eq!(res.created_date, local(1652776908000));
+        assert_eq!(res.extra.get("channel"), Some(&Value::from("WEB")));
+    }
+
+    #[test]
+    fn query_outcome_json() {
+        let outcome = QueryOutcome::Failed {
+            code: ApiError::IncorrectAccount,
+            message: "Incorrect account".to_owned(),
+            record: QueryRecord {
+                amount: Some(Thb::from_satang(10000100)),
+                created_date: Some(local(1652770078860)),
+                ..QueryRecord::default()
+            },
+        };
+        let encoded = serde_json::to_value(&outcome).expect("encoded");
+        assert_eq!(
+            encoded,
+            serde_json::json!({
+                "outcome": "failed",
+                "code": 5009,
+                "message": "Incorrect account",
+                "record": {
+                    "accname": null,
+                    "bankacc": null,
+                    "bank": null,
+                    "amount": "100001.00",
+                    "ref1": null,
+                    "ref2": null,
+                    "ref3": null,
+                    "ref4": null,
+                    "created_date": "2022-05-17T06:47:58.860+07:00",
+                    "transfer_date": null,
+                    "transfer_transaction_id": null
+                }
+            })
+        );
+        let decoded: QueryOutcome = serde_json::from_value(encoded).expect("decoded");
+        assert_eq!(decoded, outcome);
     }
 }
@@ -0,0 +1,420 @@
+//! Checks our ledger against the provider. Every `ref1` of an export is queried
+//! and the provider's record is compared with ours, see [`ReconcileReport`].
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use futures_util::stream::{self, StreamExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::batch::{invalid_rows, InvalidRow};
+use crate::error::ApiError;
+use crate::records::{self, RecordsError};
+use crate::validation::{Field, IssueKind};
+use crate::{
+    de, Bank, BankAccount, Client, QueryOutcome, QueryReq, QueryRes, Ref1, Thb, ValidationReport,
+};
+
+/// One row of the ledger export, extra columns are ignored
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct LedgerRow {
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub ref1: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub amount: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub bankacc: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_string")]
+    pub bank: Option<String>,
+}
+
+/// Payout as our ledger knows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct LedgerEntry {
+    /// Line of the row in the export
+    pub line: usize,
+    pub ref1: Ref1,
+    pub amount: Thb,
+    pub bankacc: BankAccount,
+    pub bank: Bank,
+}
+
+impl LedgerRow {
+    pub fn into_entry(self, line: usize) -> Result<LedgerEntry, ValidationReport> {
+        let mut report = ValidationReport::default();
+        let ref1 = parse(&mut report, Field::Ref1, self.ref1, |s| {
+            s.parse::<Ref1>().map_err(|e| e.to_string())
+        });
+        let amount = parse(&mut report, Field::Amount, self.amount, |s| {
+            s.parse::<Thb>().map_err(|e| e.to_string())
+        });
+        let bankacc = parse(&mut report, Field::Bankacc, self.bankacc, |s| {
+            BankAccount::from_digits(s).map_err(|e| e.to_string())
+        });
+        let bank = parse(&mut report, Field::Bank, self.bank, |s| {
+            Bank::parse(s).ok_or(format!("Unknown bank: {s}"))
+        });
+        match (ref1, amount, bankacc, bank) {
+            (Some(ref1), Some(amount), Some(bankacc), Some(bank)) => Ok(LedgerEntry {
+                line,
+                ref1,
+                amount,
+                bankacc,
+                bank,
+            }),
+            _ => Err(report),
+        }
+    }
+}
+
+fn parse<T>(
+    report: &mut ValidationReport,
+    field: Field,
+    value: Option<String>,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+) -> Option<T> {
+    let Some(value) = value.filter(|v| !v.trim().is_empty()) else {
+        report.push(field, IssueKind::Missing, "Field is required");
+        return None;
+    };
+    parse(value.trim())
+        .map_err(|e| report.push(field, IssueKind::InvalidFormat, e))
+        .ok()
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum ReconcileError {
+    #[error(transparent)]
+    Records(#[from] RecordsError),
+    #[error("{}", invalid_rows(.0))]
+    Invalid(Vec<InvalidRow>),
+}
+
+/// Field where the provider disagrees with the ledger
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Mismatch {
+    pub field: Field,
+    /// Value in the ledger
+    pub ours: String,
+    /// Value of the provider
+    pub theirs: String,
+}
+
+/// Ledger entry that doesn't match a settled transfer of the provider
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Discrepancy {
+    pub line: usize,
+    pub ref1: Ref1,
+    /// Status returned by the provider, `None` if there is no answer
+    pub code: Option<ApiError>,
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Result of [`Reconciler::reconcile`], every list is ordered by line
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ReconcileReport {
+    pub checked: usize,
+    /// Settled with the same amount, account and bank
+    pub matched: usize,
+    /// The provider has no transfer with the `ref1`
+    pub missing: Vec<Discrepancy>,
+    /// Settled, but amount, account or bank differ
+    pub mismatched: Vec<Discrepancy>,
+    /// Not final yet, check again later
+    pub pending: Vec<Discrepancy>,
+    /// Manual transfer (-2000) or suspected duplicate, the provider may still pay
+    pub needs_review: Vec<Discrepancy>,
+    /// Rejected by the provider, no money was paid
+    pub failed: Vec<Discrepancy>,
+    /// The query couldn't be answered, entry is not checked
+    pub errors: Vec<Discrepancy>,
+}
+
+impl ReconcileReport {
+    /// Every entry matched
+    pub fn is_clean(&self) -> bool {
+        self.matched == self.checked
+    }
+}
+
+/// Queries the provider for ledger entries, a few at a time
+#[derive(Debug, Clone)]
+pub struct Reconciler {
+    client: Client,
+    concurrency: usize,
+}
+
+impl Reconciler {
+    pub fn new(client: Client) -> Self {
+        Reconciler {
+            client,
+            concurrency: 4,
+        }
+    }
+
+    /// How many queries run at the same time, at least 1
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Reads and validates every row of a CSV or JSON lines export
+    pub fn load(path: &Path) -> Result<Vec<LedgerEntry>, ReconcileError> {
+        let rows: Vec<(usize, LedgerRow)> = records::read_file(path)?;
+        let mut entries = Vec::with_capacity(rows.len());
+        let mut invalid = vec![];
+        for (line, row) in rows {
+            match row.into_entry(line) {
+                Ok(entry) => entries.push(entry),
+                Err(report) => invalid.push(InvalidRow { line, report }),
+            }
+        }
+        if invalid.is_empty() {
+            Ok(entries)
+        } else {
+            Err(ReconcileError::Invalid(invalid))
+        }
+    }
+
+    pub async fn reconcile(&self, entries: Vec<LedgerEntry>) -> ReconcileReport {
+        let mut report = ReconcileReport {
+            checked: entries.len(),
+            ..ReconcileReport::default()
+        };
+        // The same ref1 twice in the ledger is a discrepancy on its own, the
+        // provider has only one transfer for it
+        let mut seen: HashMap<Ref1, usize> = HashMap::new();
+        let mut queries = vec![];
+        for entry in entries {
+            match seen.get(&entry.ref1) {
+                Some(first) => report.mismatched.push(Discrepancy {
+                    line: entry.line,
+                    message: Some(format!("ref1 is already used on line {first}")),
+                    ref1: entry.ref1,
+                    code: None,
+                    mismatches: vec![],
+                }),
+                None => {
+                    seen.insert(entry.ref1.clone(), entry.line);
+                    queries.push(entry);
+                }
+            }
+        }
+
+        let mut results = stream::iter(queries)
+            .map(|entry| async move {
+                let res = self
+                    .client
+                    .query(QueryReq {
+                        ref1: entry.ref1.to_string(),
+                    })
+                    .await;
+                (entry, res)
+            })
+            .buffer_unordered(self.concurrency);
+        while let Some((entry, res)) = results.next().await {
+            let discrepancy = |code, message, mismatches| Discrepancy {
+                line: entry.line,
+                ref1: entry.ref1.clone(),
+                code,
+                message,
+                mismatches,
+            };
+            match res {
+                Ok(QueryOutcome::Success(res)) => {
+                    let mismatches = compare(&entry, &res);
+                    if mismatches.is_empty() {
+                        report.matched += 1;
+                    } else {
+                        report
+                            .mismatched
+                            .push(discrepancy(Some(res.status), None, mismatches));
+                    }
+                }
+                Ok(QueryOutcome::Pending { message, .. }) => report.pending.push(discrepancy(
+                    Some(ApiError::WaitingBankResponse),
+                    Some(message),
+                    vec![],
+                )),
+                Ok(QueryOutcome::NeedsReview { code, message, .. }) => report
+                    .needs_review
+                    .push(discrepancy(Some(code), Some(message), vec![])),
+                Ok(QueryOutcome::Failed { code, message, .. }) => {
+                    report
+                        .failed
+                        .push(discrepancy(Some(code), Some(message), vec![]))
+                }
+                Ok(QueryOutcome::NotFound { code, message }) => {
+                    report
+                        .missing
+                        .push(discrepancy(Some(code), Some(message), vec![]))
+                }
+                Err(e) => {
+                    report
+                        .errors
+                        .push(discrepancy(e.api_code(), Some(e.to_string()), vec![]))
+                }
+            }
+        }
+        for list in [
+            &mut report.missing,
+            &mut report.mismatched,
+            &mut report.pending,
+            &mut report.needs_review,
+            &mut report.failed,
+            &mut report.errors,
+        ] {
+            list.sort_by_key(|d| d.line);
+        }
+        report
+    }
+}
+
+fn compare(entry: &LedgerEntry, res: &QueryRes) -> Vec<Mismatch> {
+    let mut mismatches = vec![];
+    if entry.amount != res.amount {
+        mismatches.push(Mismatch {
+            field: Field::Amount,
+            ours: entry.amount.to_string(),
+            theirs: res.amount.to_string(),
+        });
+    }
+    let same_account = BankAccount::from_digits(&res.bankacc).map_or(false, |a| a == entry.bankacc);
+    if !same_account {
+        mismatches.push(Mismatch {
+            field: Field::Bankacc,
+            ours: entry.bankacc.to_string(),
+            theirs: res.bankacc.clone(),
+        });
+    }
+    if entry.bank != res.bank {
+        mismatches.push(Mismatch {
+            field: Field::Bank,
+            ours: format!("{:?}", entry.bank),
+            theirs: format!("{:?}", res.bank),
+        });
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::RecordFormat;
+    use crate::transport::MockTransport;
+    use crate::RetryPolicy;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const LEDGER: &str = "ref1,amount,bankacc,bank,note
+ok-1,1.00,065-2-07840-9,KBANK,matches
+bad-2,2.00,0652078409,004,amount differs
+wait-3,3.00,0652078409,004,
+fail-4,4.00,0652078409,004,
+none-5,5.00,0652078409,004,
+manual-6,200000.00,0652078409,004,
+ok-1,1.00,0652078409,004,used twice
+";
+
+    fn record(ref1: &str, amount: &str) -> serde_json::Value {
+        json!({
+            "status": "1000",
+            "message": "Success",
+            "accname": "MANOP DEVELOPER",
+            "bankacc": "0652078409",
+            "bankcode": "004",
+            "amount": amount,
+            "ref1": ref1,
+            "created_date": "2022-05-17 08:41:48.320",
+            "transfer_date": "2022-05-17 08:41:50.447",
+            "transfer_transactionId": "2022051790WiXyi9Lwu0iuHgT"
+        })
+    }
+
+    #[tokio::test]
+    async fn report_lists_discrepancies() {
+        let entries: Vec<LedgerEntry> = records::read_str(LEDGER, RecordFormat::Csv)
+            .expect("read")
+            .into_iter()
+            .map(|(line, row): (usize, LedgerRow)| row.into_entry(line).expect("valid"))
+            .collect();
+        let mock = Arc::new(MockTransport::new());
+        mock.push_query(record("ok-1", "1.00"));
+        mock.push_query(record("bad-2", "20.00"));
+        mock.push_query(json!({"status": "9090", "message": "Waiting"}));
+        mock.push_query(json!({
+            "status": "5009",
+            "message": "Invalid account",
+            "bankacc": "0652078409",
+            "amount": "4.00",
+            "ref1": "fail-4"
+        }));
+        mock.push_query(json!({"status": "-1010", "message": "Not found"}));
+        mock.push_query(json!({
+            "status": "-2000",
+            "message": "Manual transfer",
+            "bankacc": "0652078409",
+            "amount": "200,000.00",
+            "ref1": "manual-6"
+        }));
+        let client = Client::builder("WEB", "CRS", "secret")
+            .transport(mock)
+            .not_found_code(ApiError::from_code(-1010))
+            .retry_policy(RetryPolicy {
+                max_attempts: 1,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                multiplier: 1.0,
+                jitter: 0.0,
+                deadline: None,
+            })
+            .build()
+            .expect("client");
+        let report = Reconciler::new(client)
+            .concurrency(1)
+            .reconcile(entries)
+            .await;
+
+        let lines = |list: &[Discrepancy]| list.iter().map(|d| d.line).collect::<Vec<_>>();
+        assert_eq!(report.checked, 7);
+        assert_eq!(report.matched, 1);
+        assert!(!report.is_clean());
+        assert_eq!(lines(&report.mismatched), vec![3, 8]);
+        assert_eq!(
+            report.mismatched[0].mismatches,
+            vec![Mismatch {
+                field: Field::Amount,
+                ours: "2.00".to_owned(),
+                theirs: "20.00".to_owned(),
+            }]
+        );
+        assert_eq!(lines(&report.pending), vec![4]);
+        assert_eq!(lines(&report.failed), vec![5]);
+        assert_eq!(lines(&report.missing), vec![6]);
+        assert_eq!(lines(&report.needs_review), vec![7]);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn invalid_rows() {
+        let rows: Vec<(usize, LedgerRow)> =
+            records::read_str("ref1,amount,bank\nx-1,abc,XYZ\n", RecordFormat::Csv).expect("read");
+        let (line, row) = rows.into_iter().next().expect("row");
+        let report = row.into_entry(line).expect_err("invalid");
+        let fields: Vec<(Field, IssueKind)> =
+            report.issues.iter().map(|i| (i.field, i.kind)).collect();
+        assert_eq!(
+            fields,
+            vec![
+                (Field::Amount, IssueKind::InvalidFormat),
+                (Field::Bankacc, IssueKind::Missing),
+                (Field::Bank, IssueKind::InvalidFormat),
+            ]
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Reading rows of payout files. CSV files must have a header line with field
+//! names, JSON lines files have one object per line. Empty CSV cells are treated
+//! as missing fields.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Error)]
+pub enum RecordsError {
+    #[error("Failed to read {path}: {error}")]
+    Io {
+        path: String,
+        error: Arc<std::io::Error>,
+    },
+    #[error("Unknown format of {0}, expected .csv, .json or .jsonl file")]
+    UnknownFormat(String),
+    #[error("Line {line}: {message}")]
+    Csv { line: usize, message: String },
+    #[error("Line {line}: {error}")]
+    Json {
+        line: usize,
+        error: Arc<serde_json::Error>,
+    },
+}
+
+/// Fields of a row with the line where it starts
+type Row = (usize, Map<String, Value>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Csv,
+    JsonLines,
+}
+
+impl RecordFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "csv" => Some(RecordFormat::Csv),
+            "json" | "jsonl" | "ndjson" => Some(RecordFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the file and decodes every row, format is chosen by file extension.
+/// Returns rows with the line number where each of them starts.
+pub fn read_file<T: DeserializeOwned>(path: &Path) -> Result<Vec<(usize, T)>, RecordsError> {
+    let format = RecordFormat::from_path(path)
+        .ok_or_else(|| RecordsError::UnknownFormat(path.display().to_string()))?;
+    let text = std::fs::read_to_string(path).map_err(|e| RecordsError::Io {
+        path: path.display().to_string(),
+        error: Arc::new(e),
+    })?;
+    read_str(&text, format)
+}
+
+pub fn read_str<T: DeserializeOwned>(
+    text: &str,
+    format: RecordFormat,
+) -> Result<Vec<(usize, T)>, RecordsError> {
+    let rows = match format {
+        RecordFormat::Csv => csv_rows(text)?,
+        RecordFormat::JsonLines => json_rows(text)?,
+    };
+    rows.into_iter()
+        .map(|(line, row)| {
+            serde_json::from_value(Value::Object(row))
+                .map(|value| (line, value))
+                .map_err(|e| RecordsError::Json {
+                    line,
+                    error: Arc::new(e),
+                })
+        })
+        .collect()
+}
+
+fn json_rows(text: &str) -> Result<Vec<Row>, RecordsError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map(|row| (i + 1, row))
+                .map_err(|e| RecordsError::Json {
+                    line: i + 1,
+                    error: Arc::new(e),
+                })
+        })
+        .collect()
+}
+
+fn csv_rows(text: &str) -> Result<Vec<Row>, RecordsError> {
+    let mut records = parse_csv(text.strip_prefix('\u{feff}').unwrap_or(text))?.into_iter();
+    let Some((_, header)) = records.next() else {
+        return Ok(vec![]);
+    };
+    let header: Vec<String> = header.iter().map(|h| h.trim().to_owned()).collect();
+    records
+        .filter(|(_, cells)| cells.iter().any(|c| !c.trim().is_empty()))
+        .map(|(line, cells)| {
+            if cells.len() != header.len() {
+                return Err(RecordsError::Csv {
+                    line,
+                    message: format!("expected {} cells, got {}", header.len(), cells.len()),
+                });
+            }
+            let row = header
+                .iter()
+                .zip(cells)
+                .filter(|(_, cell)| !cell.trim().is_empty())
+                .map(|(name, cell)| (name.clone(), Value::String(cell.trim().to_owned())))
+                .collect();
+            Ok((line, row))
+        })
+        .collect()
+}
+
+/// RFC 4180: cells are separated by commas, quoted cells may contain commas,
+/// line breaks and doubled quotes.
+fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, RecordsError> {
+    let mut records = vec![];
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => {
+                if c == '\n' {
+                    line += 1;
+                }
+                cell.push(c);
+            }
+            (false, '"') if cell.is_empty() => quoted = true,
+            (false, ',') => cells.push(std::mem::take(&mut cell)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                cells.push(std::mem::take(&mut cell));
+                records.push((record_line, std::mem::take(&mut cells)));
+                line += 1;
+                record_line = line;
+            }
+            (false, c) => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(RecordsError::Csv {
+            line: record_line,
+            message: "quoted cell is not closed".to_owned(),
+        });
+    }
+    if !cell.is_empty() || !cells.is_empty() {
+        cells.push(cell);
+        records.push((record_line, cells));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Row {
+        name: String,
+        amount: Option<String>,
+    }
+
+    fn row(name: &str, amount: Option<&str>) -> Row {
+        Row {
+            name: name.to_owned(),
+            amount: amount.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let text = "name,amount\r\n\"Tangngam, Manop\",\"1,000.50\"\n\n\"say \"\"hi\"\"\nthere\",\nplain,10";
+        let rows: Vec<(usize, Row)> = read_str(text, RecordFormat::Csv).expect("parsed");
+        assert_eq!(
+            rows,
+            vec![
+                (2, row("Tangngam, Manop", Some("1,000.50"))),
+                (4, row("say \"hi\"\nthere", None)),
+                (6, row("plain", Some("10"))),
+            ]
+        );
+        assert!(matches!(
+            read_str::<Row>("name,amount\nx", RecordFormat::Csv),
+            Err(RecordsError::Csv { line: 2, .. })
+        ));
+        assert!(read_str::<Row>("name\n\"x", RecordFormat::Csv).is_err());
+    }
+
+    #[test]
+    fn json_lines() {
+        let text = "{\"name\": \"a\", \"amount\": \"1.00\"}\n\n{\"name\": \"b\"}\n";
+        let rows: Vec<(usize, Row)> = read_str(text, RecordFormat::JsonLines).expect("parsed");
+        assert_eq!(rows, vec![(1, row("a", Some("1.00"))), (3, row("b", None))]);
+        assert!(matches!(
+            read_str::<Row>("{\"amount\": \"1\"}", RecordFormat::JsonLines),
+            Err(RecordsError::Json { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+//! Generator of unique `ref1` values. Layout is `<prefix><time><random>`, where
+//! time is milliseconds since Unix epoch and random is 50 bits, both written in
+//! Crockford's base32 with 10 characters each, like in ULID. Values of one
+//! generator with the same prefix sort in the order they were made.
+
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, TimeZone, Utc};
+use rand::Rng;
+
+use crate::fields::{FieldError, Ref1, REF1_MAX_LEN};
+
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const TIME_LEN: usize = 10;
+const RANDOM_LEN: usize = 10;
+const RANDOM_MAX: u64 = (1 << (5 * RANDOM_LEN)) - 1;
+
+/// Longest prefix that still fits into [`REF1_MAX_LEN`]
+pub const REF1_PREFIX_MAX_LEN: usize = REF1_MAX_LEN - TIME_LEN - RANDOM_LEN;
+
+#[derive(Debug, Default)]
+pub struct Ref1Generator {
+    prefix: String,
+    /// Time and random part of the last value
+    last: Mutex<(u64, u64)>,
+}
+
+impl Ref1Generator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prefix may contain the same characters as `ref1` and be up to
+    /// [`REF1_PREFIX_MAX_LEN`] long. Example: "pay-"
+    pub fn with_prefix(prefix: &str) -> Result<Self, FieldError> {
+        if prefix.len() > REF1_PREFIX_MAX_LEN {
+            return Err(FieldError::RefPrefix(prefix.to_owned()));
+        }
+        if !prefix.is_empty() {
+            prefix.parse::<Ref1>()?;
+        }
+        Ok(Ref1Generator {
+            prefix: prefix.to_owned(),
+            ..Self::default()
+        })
+    }
+
+    pub fn generate(&self) -> Ref1 {
+        self.generate_at(Utc::now().timestamp_millis().max(0) as u64)
+    }
+
+    fn generate_at(&self, now_ms: u64) -> Ref1 {
+        let mut last = self.last.lock().expect("ref1 generator lock");
+        // Within the same millisecond the random part is incremented, so values
+        // stay unique and ordered
+        let (time, random) = if now_ms <= last.0 {
+            if last.1 < RANDOM_MAX {
+                (last.0, last.1 + 1)
+            } else {
+                (last.0 + 1, rand::thread_rng().gen_range(0..=RANDOM_MAX / 2))
+            }
+        } else {
+            (now_ms, rand::thread_rng().gen_range(0..=RANDOM_MAX / 2))
+        };
+        *last = (time, random);
+        let value = format!(
+            "{}{}{}",
+            self.prefix,
+            encode(time, TIME_LEN),
+            encode(random, RANDOM_LEN)
+        );
+        value.parse().expect("generated ref1 is valid")
+    }
+
+    /// Time when the value was generated. `None` if the value doesn't have the
+    /// generated layout, though other values may decode to a meaningless time.
+    pub fn timestamp(ref1: &Ref1) -> Option<DateTime<Utc>> {
+        let s = ref1.as_str();
+        let start = s.len().checked_sub(TIME_LEN + RANDOM_LEN)?;
+        let millis = decode(s.get(start..start + TIME_LEN)?)?;
+        decode(s.get(start + TIME_LEN..)?)?;
+        Utc.timestamp_millis_opt(i64::try_from(millis).ok()?)
+            .single()
+    }
+}
+
+impl Ref1 {
+    /// New unique value without prefix, see [`Ref1Generator`]
+    pub fn generate() -> Ref1 {
+        static GENERATOR: OnceLock<Ref1Generator> = OnceLock::new();
+        GENERATOR.get_or_init(Ref1Generator::new).generate()
+    }
+}
+
+fn encode(mut value: u64, len: usize) -> String {
+    let mut out = vec![b'0'; len];
+    for c in out.iter_mut().rev() {
+        *c = ALPHABET[(value & 31) as usize];
+        value >>= 5;
+    }
+    String::from_utf8(out).expect("ascii")
+}
+
+fn decode(s: &str) -> Option<u64> {
+    s.chars().try_fold(0u64, |acc, c| {
+        let c = match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        let digit = ALPHABET.iter().position(|a| *a as char == c)?;
+        acc.checked_mul(32)?.checked_add(digit as u64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_and_timestamp() {
+        let gen = Ref1Generator::with_prefix("pay-").expect("prefix");
+        let ref1 = gen.generate_at(1652776908320);
+        assert_eq!(ref1.as_str().len(), 24);
+        assert!(ref1.as_str().starts_with("pay-01G38JWDH0"));
+        assert_eq!(
+            Ref1Generator::timestamp(&ref1).map(|t| t.timestamp_millis()),
+            Some(1652776908320)
+        );
+        assert_eq!(
+            Ref1Generator::timestamp(&"202205170841".parse().expect("ref1")),
+            None
+        );
+    }
+
+    #[test]
+    fn monotonic() {
+        let gen = Ref1Generator::new();
+        let mut values: Vec<Ref1> = (0..1000).map(|_| gen.generate_at(1000)).collect();
+        values.push(gen.generate_at(999));
+        values.push(gen.generate_at(1001));
+        assert!(values.windows(2).all(|w| w[0] < w[1]));
+
+        let generated = Ref1::generate();
+        assert_eq!(generated.as_str().len(), TIME_LEN + RANDOM_LEN);
+        assert!(Ref1Generator::timestamp(&generated).is_some());
+    }
+
+    #[test]
+    fn prefix_validation() {
+        assert!(Ref1Generator::with_prefix("service01_").is_ok());
+        assert!(matches!(
+            Ref1Generator::with_prefix("service012_"),
+            Err(FieldError::RefPrefix(_))
+        ));
+        assert!(matches!(
+            Ref1Generator::with_prefix("a b"),
+            Err(FieldError::RefCharset(_))
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How [`crate::Client`] repeats calls that failed with network error or with a code
+/// that means "try again later". Other failures are returned immediately.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one. 1 disables retries.
+    pub max_attempts: u32,
+    /// Delay before the second attempt
+    pub initial_backoff: Duration,
+    /// Upper bound of delay between attempts
+    pub max_backoff: Duration,
+    /// Factor by which delay grows after each attempt
+    pub multiplier: f64,
+    /// Fraction of delay that is randomized, from 0.0 (none) to 1.0 (full jitter)
+    pub jitter: f64,
+    /// Don't start new attempts when this much time passed since the first one
+    pub deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Single attempt, no retries
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the given attempt, where attempt 2 is the first retry
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(2).min(i32::MAX as u32) as i32;
+        let base = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exp);
+        let capped = base.min(self.max_backoff.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let randomized = if jitter > 0.0 {
+            capped * (1.0 - jitter * rand::thread_rng().gen::<f64>())
+        } else {
+            capped
+        };
+        Duration::from_secs_f64(randomized.max(0.0))
+    }
+
+    /// Whether one more attempt fits into attempts limit and the deadline
+    pub fn allows(&self, attempt: u32, elapsed: Duration) -> bool {
+        attempt <= self.max_attempts
+            && self
+                .deadline
+                .map_or(true, |d| elapsed + self.backoff_upper(attempt) <= d)
+    }
+
+    fn backoff_upper(&self, attempt: u32) -> Duration {
+        RetryPolicy {
+            jitter: 0.0,
+            ..self.clone()
+        }
+        .backoff(attempt)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            deadline: Some(Duration::from_secs(120)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ApiError, Error};
+    use crate::fields::BankAccount;
+    use crate::money::Thb;
+    use crate::transport::{MockTransport, TransportError};
+    use crate::{Bank, Client, TransferReq, PAYOUT_PATH, QUERY_PATH};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            multiplier: 2.0,
+            jitter: 0.5,
+            deadline: None,
+        }
+    }
+
+    /// The provider doesn't document its not-found status, the mock uses this one
+    const NOT_FOUND: i32 = -1010;
+
+    fn client(mock: Arc<MockTransport>) -> Client {
+        Client::builder("WEB", "CRS", "secret")
+            .transport(mock)
+            .retry_policy(policy())
+            .not_found_code(ApiError::from_code(NOT_FOUND))
+            .build()
+            .expect("client")
+    }
+
+    fn request() -> TransferReq {
+        TransferReq {
+            bankacc: BankAccount::new(Bank::Kasikorn, "0652078409").expect("account"),
+            bank: Bank::Kasikorn,
+            accname: "Manop Tangngam".to_owned(),
+            amount: Thb::from_satang(100),
+            mobileno: "0805933181".parse().expect("mobile"),
+            transaction_by: "Jack Developer".to_owned(),
+            ref1: "202205170841".parse().expect("ref1"),
+            ref2: None,
+            ref3: None,
+            ref4: None,
+            line_token: None,
+            email: None,
+            allow_duplicate: false,
+        }
+    }
+
+    fn paths(mock: &MockTransport) -> Vec<String> {
+        mock.requests().into_iter().map(|r| r.path).collect()
+    }
+
+    #[test]
+    fn backoff_growth() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(2), Duration::from_millis(500));
+        assert_eq!(policy.backoff(3), Duration::from_secs(1));
+        assert_eq!(policy.backoff(30), Duration::from_secs(30));
+        assert!(policy.allows(3, Duration::ZERO));
+        assert!(!policy.allows(4, Duration::ZERO));
+        assert!(!policy.allows(2, Duration::from_secs(120)));
+    }
+
+    #[tokio::test]
+    async fn retry_finds_delivered_transfer() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 9091, "message": "no response"}));
+        mock.push_query(json!({
+            "status": "1000",
+            "message": "Success",
+            "accname": "MANOP DEVELOPER",
+            "bankacc": "0652078409",
+            "bankcode": "004",
+            "amount": "1.00",
+            "ref1": "202205170841",
+            "ref2": "",
+            "ref3": "",
+            "ref4": "",
+            "created_date": "2022-05-17 08:41:48.320",
+            "transfer_date": "2022-05-17 08:41:50.447",
+            "transfer_transactionId": "2022051790WiXyi9Lwu0iuHgT"
+        }));
+        let res = client(mock.clone())
+            .transfer(request())
+            .await
+            .expect("transfer");
+        assert_eq!(res.transaction_id, "2022051790WiXyi9Lwu0iuHgT");
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, QUERY_PATH]);
+    }
+
+    #[tokio::test]
+    async fn retry_resends_undelivered_transfer() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 1999, "message": "cannot process"}));
+        mock.push_query(json!({"status": NOT_FOUND, "message": "not found"}));
+        mock.push_transfer(json!({
+            "status": 1000,
+            "message": "Success",
+            "transaction_id": "2022030288DtbRwK0IKr536t4",
+            "transactionDate_time": "2023-09-20T17:35:13",
+        }));
+        let res = client(mock.clone())
+            .transfer(request())
+            .await
+            .expect("transfer");
+        assert_eq!(res.transaction_id, "2022030288DtbRwK0IKr536t4");
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, QUERY_PATH, PAYOUT_PATH]);
+    }
+
+    #[tokio::test]
+    async fn terminal_code_is_not_retried() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": -1009, "message": "Balance is not enough"}));
+        let res = client(mock.clone()).transfer(request()).await;
+        assert!(res.is_err());
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH]);
+    }
+
+    #[tokio::test]
+    async fn undelivered_transfer_is_resent_without_query() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_response(
+            PAYOUT_PATH,
+            Err(TransportError::NotSent("connection refused".to_owned())),
+        );
+        mock.push_transfer(json!({
+            "status": 1000,
+            "message": "Success",
+            "transaction_id": "2022030288DtbRwK0IKr536t4",
+            "transactionDate_time": "2023-09-20T17:35:13",
+        }));
+        let res = client(mock.clone())
+            .transfer(request())
+            .await
+            .expect("transfer");
+        assert_eq!(res.transaction_id, "2022030288DtbRwK0IKr536t4");
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, PAYOUT_PATH]);
+    }
+
+    #[tokio::test]
+    async fn lost_response_stays_unknown() {
+        let mock = Arc::new(MockTransport::new());
+        let lost = || Err(TransportError::NoResponse("connection reset".to_owned()));
+        mock.push_response(PAYOUT_PATH, lost());
+        mock.push_response(QUERY_PATH, lost());
+        // Nothing scripted for the last query, so it is not delivered
+        let res = client(mock.clone()).transfer(request()).await;
+        assert!(matches!(res, Err(Error::OutcomeUnknown(_))), "{res:?}");
+        assert!(res.expect_err("unknown").is_outcome_unknown());
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, QUERY_PATH, QUERY_PATH]);
+    }
+
+    #[tokio::test]
+    async fn unclear_query_is_not_resent() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 9091, "message": "no response"}));
+        mock.push_query(json!({"status": "-1002", "message": "Invalid Authorization"}));
+        mock.push_query(json!({"status": NOT_FOUND - 1, "message": "who knows"}));
+        let res = client(mock.clone()).transfer(request()).await;
+        assert!(matches!(res, Err(Error::OutcomeUnknown(_))), "{res:?}");
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, QUERY_PATH, QUERY_PATH]);
+
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 9091, "message": "no response"}));
+        mock.push_query(json!({"status": "-2000", "message": "Manual transfer"}));
+        let res = client(mock.clone()).transfer(request()).await;
+        assert_eq!(
+            res.expect_err("manual").api_code(),
+            Some(ApiError::ManualTransfer)
+        );
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, QUERY_PATH]);
+    }
+
+    #[tokio::test]
+    async fn rejected_transfer_is_not_resent() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({"status": 9091, "message": "no response"}));
+        mock.push_query(json!({
+            "status": "-1009",
+            "message": "Balance is not enough",
+            "ref1": "202205170841",
+            "amount": "1.00"
+        }));
+        let res = client(mock.clone()).transfer(request()).await;
+        assert_eq!(
+            res.expect_err("rejected").api_code(),
+            Some(ApiError::InsufficientBalance)
+        );
+        assert_eq!(paths(&mock), vec![PAYOUT_PATH, QUERY_PATH]);
+    }
+}
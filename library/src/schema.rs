@@ -0,0 +1,77 @@
+//! JSON schemas of the public types, so other services can validate payloads
+//! against the same definitions the client uses.
+
+use clap::ValueEnum;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+
+use crate::error::{ApiError, ApiErrorCategory, ResponseInfo};
+use crate::journal::JournalRecord;
+use crate::qr::SlipQr;
+use crate::{
+    Bank, PayoutOutcome, QueryOutcome, QueryReq, QueryRes, ReconcileReport, TransferReq,
+    TransferRes, ValidationReport,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum SchemaType {
+    TransferReq,
+    TransferRes,
+    QueryReq,
+    QueryRes,
+    QueryOutcome,
+    PayoutOutcome,
+    JournalRecord,
+    ReconcileReport,
+    SlipQr,
+    Bank,
+    ApiError,
+    ApiErrorCategory,
+    ResponseInfo,
+    ValidationReport,
+}
+
+impl SchemaType {
+    pub fn schema(self) -> RootSchema {
+        match self {
+            SchemaType::TransferReq => schema_for!(TransferReq),
+            SchemaType::TransferRes => schema_for!(TransferRes),
+            SchemaType::QueryReq => schema_for!(QueryReq),
+            SchemaType::QueryRes => schema_for!(QueryRes),
+            SchemaType::QueryOutcome => schema_for!(QueryOutcome),
+            SchemaType::PayoutOutcome => schema_for!(PayoutOutcome),
+            SchemaType::JournalRecord => schema_for!(JournalRecord),
+            SchemaType::ReconcileReport => schema_for!(ReconcileReport),
+            SchemaType::SlipQr => schema_for!(SlipQr),
+            SchemaType::Bank => schema_for!(Bank),
+            SchemaType::ApiError => schema_for!(ApiError),
+            SchemaType::ApiErrorCategory => schema_for!(ApiErrorCategory),
+            SchemaType::ResponseInfo => schema_for!(ResponseInfo),
+            SchemaType::ValidationReport => schema_for!(ValidationReport),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn schemas_are_generated() {
+        for t in SchemaType::value_variants() {
+            let schema = serde_json::to_value(t.schema()).expect("encoded");
+            assert!(schema.get("$schema").is_some(), "{t:?}");
+        }
+        let req = serde_json::to_value(SchemaType::TransferReq.schema()).expect("encoded");
+        let required = req["required"].as_array().expect("required");
+        for field in ["bankacc", "bank", "amount", "mobileno", "ref1"] {
+            assert!(required.contains(&Value::from(field)), "{field}");
+        }
+        assert_eq!(req["definitions"]["Thb"]["type"], "string");
+        assert_eq!(
+            req["definitions"]["Ref1"]["pattern"],
+            "^[A-Za-z0-9_-]{1,30}$"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::{Debug, Display};
+
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+/// Fields of request and response bodies that are masked in logs
+const MASKED_FIELDS: [&str; 3] = ["bankacc", "mobileno", "email"];
+
+/// How many trailing characters of a masked value stay visible
+const VISIBLE_SUFFIX: usize = 4;
+
+/// String that is not printed by `Debug` and `Display` and is wiped from memory on drop.
+/// Used for the API key, which is a bearer JWT.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: &str) -> Self {
+        Secret(Zeroizing::new(value.to_owned()))
+    }
+
+    /// Access the secret value. Don't log it.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(Zeroizing::new(value))
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+/// Replace all but last characters with '*'. Example: "0652078409" -> "******8409"
+pub fn mask(value: &str) -> String {
+    let len = value.chars().count();
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i + VISIBLE_SUFFIX < len { '*' } else { c })
+        .collect()
+}
+
+/// Mask personal data in JSON body before it is logged
+pub(crate) fn mask_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                match v {
+                    Value::String(s) if MASKED_FIELDS.contains(&key.as_str()) => *s = mask(s),
+                    _ => mask_json(v),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(mask_json),
+        _ => (),
+    }
+}
+
+/// Body in the form suitable for `trace!`
+pub(crate) fn masked_body(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(mut value) => {
+            mask_json(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("<{} bytes of not JSON body>", body.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_redacted() {
+        let secret = Secret::new("eyJhbGciOiJIUzI1NiJ9");
+        assert_eq!(format!("{secret:?}"), "Secret([REDACTED])");
+        assert_eq!(format!("{secret}"), "[REDACTED]");
+        assert_eq!(secret.expose(), "eyJhbGciOiJIUzI1NiJ9");
+
+        let client = crate::Client::new("WEB", "CRS", "eyJhbGciOiJIUzI1NiJ9");
+        assert!(!format!("{client:?}").contains("eyJhbGciOiJIUzI1NiJ9"));
+    }
+
+    #[test]
+    fn body_masking() {
+        let body = "{\"bankacc\":\"0652078409\",\"mobileno\":\"0805933181\",\"email\":\"a@b.io\",\"accname\":\"Manop\",\"amount\":10}";
+        let masked: Value = serde_json::from_str(&masked_body(body)).expect("json");
+        assert_eq!(masked["bankacc"], "******8409");
+        assert_eq!(masked["mobileno"], "******3181");
+        assert_eq!(masked["email"], "**b.io");
+        assert_eq!(masked["accname"], "Manop");
+        assert_eq!(mask("123"), "123");
+    }
+}
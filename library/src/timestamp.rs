@@ -0,0 +1,82 @@
+//! Timestamps in provider responses. The provider sends local time of Thailand
+//! without offset, "2022-05-17 08:41:48.320" from inquery and "2023-09-20T17:35:13"
+//! from payout, but both endpoints were seen sending the other format. All of them
+//! are parsed here and anchored to UTC+7.
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+
+/// Asia/Bangkok has no daylight saving, so it is always UTC+7
+pub const BANGKOK_OFFSET_SECS: i32 = 7 * 3600;
+
+/// Time as reported by the provider, in Asia/Bangkok offset
+pub type BangkokTime = DateTime<FixedOffset>;
+
+const FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%d/%m/%Y %H:%M:%S%.f",
+    "%Y%m%d%H%M%S",
+];
+
+pub fn bangkok() -> FixedOffset {
+    FixedOffset::east_opt(BANGKOK_OFFSET_SECS).expect("valid offset")
+}
+
+/// Current time in Bangkok
+pub fn now() -> BangkokTime {
+    Utc::now().with_timezone(&bangkok())
+}
+
+/// Treats the wall clock time as Bangkok local time
+pub fn from_local(local: NaiveDateTime) -> BangkokTime {
+    bangkok()
+        .from_local_datetime(&local)
+        .single()
+        .expect("fixed offset has no gaps")
+}
+
+pub fn to_utc(time: &BangkokTime) -> DateTime<Utc> {
+    time.with_timezone(&Utc)
+}
+
+/// Parses any of the known formats. Timestamps without offset are Bangkok local
+/// time, timestamps with explicit offset are converted to it.
+pub fn parse(s: &str) -> Result<BangkokTime, String> {
+    let s = s.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.with_timezone(&bangkok()));
+    }
+    FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .map(from_local)
+        .ok_or_else(|| format!("expected one of formats {FORMATS:?} or RFC 3339"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_formats() {
+        let expected = Utc.timestamp_millis_opt(1652751708000).unwrap();
+        for input in [
+            "2022-05-17 08:41:48",
+            "2022-05-17T08:41:48",
+            "2022-05-17T08:41:48.000",
+            "17/05/2022 08:41:48",
+            "20220517084148",
+            "2022-05-17T08:41:48+07:00",
+            "2022-05-17T01:41:48Z",
+        ] {
+            let parsed = parse(input).expect(input);
+            assert_eq!(to_utc(&parsed), expected, "{input}");
+            assert_eq!(parsed.offset(), &bangkok(), "{input}");
+        }
+        assert_eq!(
+            parse("2022-05-17 08:41:48.320").map(|t| t.to_rfc3339()),
+            Ok("2022-05-17T08:41:48.320+07:00".to_owned())
+        );
+        assert!(parse("17 May 2022").is_err());
+    }
+}
@@ -1,26 +1,35 @@
 use crate::error::ApiError;
+use crate::fields::{BankAccount, Ref1, ThaiMobile};
+use crate::money::Thb;
+use crate::qr::{QrError, SlipQr};
+use crate::validation::TransferReqBuilder;
 
 use super::bank::*;
-use chrono::NaiveDateTime;
+use crate::de;
+use crate::timestamp::{self, BangkokTime};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use thiserror::Error;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct TransferReq {
     /// Bank account. Example: “0652078409"
-    pub bankacc: String,
+    pub bankacc: BankAccount,
     /// Which bank to withdraw to.
     pub bank: Bank,
     /// Name of person owning the account: Manop Tangngam"
     pub accname: String,
     /// Amount of THB to transfer. Example: 1000.50
-    pub amount: f64,
+    pub amount: Thb,
     /// Thailand phone number. Example: 0805933181"
-    pub mobileno: String,
+    pub mobileno: ThaiMobile,
     /// Name of entity that makes the transaction. Example: "Jack Developer"
     pub transaction_by: String,
     /// External ID of the transaction. Example: "123456789012345678“
-    pub ref1: String,
+    pub ref1: Ref1,
     /// Additional external data
     pub ref2: Option<String>,
     /// Additional external data
@@ -31,6 +40,17 @@ pub struct TransferReq {
     pub line_token: Option<String>,
     /// Email address
     pub email: Option<String>,
+    /// Send even if [`crate::dedup::DuplicateDetector`] suspects a repeat of a
+    /// recent transfer. Not sent to the API.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_duplicate: bool,
+}
+
+impl TransferReq {
+    /// Build request from raw values and get all validation problems at once
+    pub fn builder() -> TransferReqBuilder {
+        TransferReqBuilder::new()
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -44,7 +64,8 @@ pub struct TransferReqInner {
     /// Name of person owning the account: Manop Tangngam"
     accname: String,
     /// Amount of THB to transfer. Example: 1000.50
-    amount: f64,
+    #[serde(serialize_with = "crate::money::as_number")]
+    amount: Thb,
     /// Thailand phone number. Example: 0805933181"
     mobileno: String,
     /// Name of entity that makes the transaction. Example: "Jack Developer"
@@ -72,14 +93,14 @@ pub struct TransferReqInner {
 impl From<TransferReq> for TransferReqInner {
     fn from(value: TransferReq) -> Self {
         TransferReqInner {
-            bankacc: value.bankacc,
+            bankacc: value.bankacc.into(),
             bankcode: format!("{:0>3}", value.bank.to_code()),
             bankname: format!("{}", value.bank),
             accname: value.accname,
             amount: value.amount,
-            mobileno: value.mobileno,
+            mobileno: value.mobileno.into(),
             transaction_by: value.transaction_by,
-            ref1: value.ref1,
+            ref1: value.ref1.into(),
             ref2: value.ref2,
             ref3: value.ref3,
             ref4: value.ref4,
@@ -89,16 +110,21 @@ impl From<TransferReq> for TransferReqInner {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct TransferRes {
     pub payout_ref: Option<String>,
     pub transaction_id: String,
-    pub transaction_date_time: NaiveDateTime,
+    /// Provider's local time, see [`TransferRes::transaction_date_time_utc`]
+    pub transaction_date_time: BangkokTime,
     pub qrstring: Option<String>,
+    /// Fields of the response this library doesn't know about
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Value>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct TransferResInner {
+    #[serde(deserialize_with = "de::status")]
     status: i32,
     message: String,
     payout_ref: Option<String>,
@@ -106,6 +132,8 @@ pub struct TransferResInner {
     #[serde(rename = "transactionDate_time")]
     transaction_date_time: Option<String>,
     qrstring: Option<String>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
 }
 
 #[derive(Debug, Clone, PartialEq, Error)]
@@ -118,6 +146,27 @@ pub enum TransferConvError {
     SuccessNones(String),
 }
 
+impl TransferResInner {
+    /// Provider's explanation of the status
+    pub(crate) fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl TransferRes {
+    pub fn transaction_date_time_utc(&self) -> DateTime<Utc> {
+        timestamp::to_utc(&self.transaction_date_time)
+    }
+
+    /// Decodes [`TransferRes::qrstring`], fails if its checksum doesn't match.
+    /// Strings that don't follow the EMVCo layout can't be decoded, e.g. the
+    /// sample in the provider's docs, whose tag 00 is cut short, fails with
+    /// [`QrError::Truncated`]. The raw string stays in `qrstring` then.
+    pub fn slip(&self) -> Option<Result<SlipQr, QrError>> {
+        self.qrstring.as_deref().map(str::parse)
+    }
+}
+
 impl TryFrom<TransferResInner> for TransferRes {
     type Error = TransferConvError;
 
@@ -136,12 +185,10 @@ impl TryFrom<TransferResInner> for TransferRes {
                 transaction_id: value
                     .transaction_id
                     .ok_or(TransferConvError::SuccessNones("transaction_id".to_owned()))?,
-                transaction_date_time: NaiveDateTime::parse_from_str(
-                    &date_time_str,
-                    "%Y-%m-%dT%H:%M:%S",
-                )
-                .map_err(|e| TransferConvError::TimestampParse(date_time_str, e.to_string()))?,
+                transaction_date_time: timestamp::parse(&date_time_str)
+                    .map_err(|e| TransferConvError::TimestampParse(date_time_str, e))?,
                 qrstring: value.qrstring,
+                extra: value.extra,
             })
         } else {
             Err(TransferConvError::Api(code))
@@ -152,6 +199,7 @@ impl TryFrom<TransferResInner> for TransferRes {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::NaiveDateTime;
 
     #[test]
     fn transfer_request_example() {
@@ -166,26 +214,29 @@ mod tests {
             \"ref1\": \"123456789012345678\"
         }";
         let datum = TransferReq {
-            bankacc: "0652078409".to_owned(),
+            bankacc: BankAccount::new(Bank::Kasikorn, "0652078409").expect("account"),
             bank: Bank::Kasikorn,
             accname: "Manop Tangngam".to_owned(),
-            amount: 1000.5,
-            mobileno: "0805933181".to_owned(),
+            amount: Thb::from_satang(100050),
+            mobileno: "0805933181".parse().expect("mobile"),
             transaction_by: "Jack Developer".to_owned(),
-            ref1: "123456789012345678".to_owned(),
+            ref1: "123456789012345678".parse().expect("ref1"),
             ref2: None,
             ref3: None,
             ref4: None,
             line_token: None,
             email: None,
+            allow_duplicate: false,
         };
         let datum_inner: TransferReqInner = datum.into();
 
         let example_json: serde_json::Value = serde_json::from_str(example).expect("json");
+        let encoded = serde_json::to_string(&datum_inner).expect("encoded");
         assert_eq!(
             example_json,
-            serde_json::to_value(&datum_inner).expect("encoded")
+            serde_json::from_str::<serde_json::Value>(&encoded).expect("decoded")
         );
+        assert!(encoded.contains("\"amount\":1000.50"));
     }
 
     #[test]
@@ -201,16 +252,35 @@ mod tests {
         let datum = TransferRes {
             payout_ref: Some("2022030288DtbRwK0IKr536t4".to_owned()),
             transaction_id: "2022030288DtbRwK0IKr536t4".to_owned(),
-            transaction_date_time: NaiveDateTime::from_timestamp_millis(1695231313000)
-                .expect("timestamp"),
+            transaction_date_time: timestamp::from_local(
+                NaiveDateTime::from_timestamp_millis(1695231313000).expect("timestamp"),
+            ),
             qrstring: Some("00460006022030288DtbRwK0IKr536t45102TH91042337".to_owned()),
+            extra: BTreeMap::new(),
         };
 
-        let example_inner: TransferResInner = serde_json::from_str(&example).expect("parsed");
+        let example_inner: TransferResInner = serde_json::from_str(example).expect("parsed");
         let example_conv: Result<TransferRes, TransferConvError> = example_inner.try_into();
         assert_eq!(example_conv, Ok(datum));
     }
 
+    #[test]
+    fn transfer_response_slip() {
+        let mut res = TransferRes {
+            payout_ref: None,
+            transaction_id: "2022030288DtbRwK0IKr536t4".to_owned(),
+            transaction_date_time: timestamp::from_local(
+                NaiveDateTime::from_timestamp_millis(1695231313000).expect("timestamp"),
+            ),
+            qrstring: None,
+            extra: BTreeMap::new(),
+        };
+        assert_eq!(res.slip(), None);
+        // The only sample the provider documents is cut short
+        res.qrstring = Some("00460006022030288DtbRwK0IKr536t45102TH91042337".to_owned());
+        assert_eq!(res.slip(), Some(Err(QrError::Truncated(0))));
+    }
+
     #[test]
     fn transfer_response_failure() {
         let example = "{
@@ -224,9 +294,10 @@ mod tests {
             transaction_id: None,
             transaction_date_time: None,
             qrstring: None,
+            extra: BTreeMap::new(),
         };
 
-        let example_inner: TransferResInner = serde_json::from_str(&example).expect("parsed");
+        let example_inner: TransferResInner = serde_json::from_str(example).expect("parsed");
         let example_conv: Result<TransferRes, TransferConvError> = example_inner.clone().try_into();
         assert_eq!(example_inner, datum);
         assert_eq!(
@@ -234,4 +305,22 @@ mod tests {
             Err(TransferConvError::Api(ApiError::from_code(9091)))
         );
     }
+
+    #[test]
+    fn transfer_response_lenient() {
+        let example = "{
+            \"status\": \"1000\",
+            \"message\": \"Success\",
+            \"transaction_id\": \"2022030288DtbRwK0IKr536t4\",
+            \"transactionDate_time\": \"2023-09-20 17:35:13.000\",
+            \"fee\": 10
+            }";
+        let example_inner: TransferResInner = serde_json::from_str(example).expect("parsed");
+        let res: TransferRes = example_inner.try_into().expect("converted");
+        assert_eq!(
+            res.transaction_date_time_utc().timestamp_millis(),
+            1695231313000 - 7 * 3600 * 1000
+        );
+        assert_eq!(res.extra.get("fee"), Some(&Value::from(10)));
+    }
 }
@@ -0,0 +1,291 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use thiserror::Error;
+
+use crate::secret::{masked_body, Secret};
+use crate::{ONE_TWO_PAY_URL, PAYOUT_PATH, QUERY_PATH};
+
+/// Request that [`crate::Client`] asks a transport to deliver.
+#[derive(Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    /// Path relative to API root. Example: "/payout"
+    pub path: String,
+    /// Value of the Authorization header, the API key
+    pub authorization: Secret,
+    /// Other auth headers: Partnercode and Channel
+    pub headers: Vec<(String, String)>,
+    /// JSON encoded body
+    pub body: String,
+}
+
+/// Hides the API key and personal data in the body
+impl Debug for HttpRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpRequest")
+            .field("path", &self.path)
+            .field("authorization", &self.authorization)
+            .field("headers", &self.headers)
+            .field("body", &masked_body(&self.body))
+            .finish()
+    }
+}
+
+/// Raw answer of the API before it is decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum TransportError {
+    #[error("{0}")]
+    Reqwest(Arc<reqwest::Error>),
+    #[error("Mock transport has no scripted response for {0}")]
+    MockExhausted(String),
+    /// For custom transports: the request certainly didn't leave the process
+    #[error("Request was not sent: {0}")]
+    NotSent(String),
+    /// For custom transports: the request was sent, but no answer came back
+    #[error("No response to the request: {0}")]
+    NoResponse(String),
+}
+
+impl TransportError {
+    /// True when the server certainly didn't receive the request, e.g. DNS
+    /// failure or refused connection. Timeouts and dropped connections after
+    /// the request was written are not, the server may have processed it.
+    pub fn is_not_delivered(&self) -> bool {
+        match self {
+            TransportError::Reqwest(e) => e.is_builder() || e.is_connect(),
+            TransportError::MockExhausted(_) | TransportError::NotSent(_) => true,
+            TransportError::NoResponse(_) => false,
+        }
+    }
+}
+
+/// The way requests reach the 1-2-Pay API. The default one is [`ReqwestTransport`],
+/// tests can use [`MockTransport`] to run without network.
+#[async_trait]
+pub trait Transport: Debug + Send + Sync {
+    async fn post(&self, req: HttpRequest) -> Result<HttpResponse, TransportError>;
+}
+
+/// Sends requests over HTTP with a shared connection pool.
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(base_url: &str, http: reqwest::Client) -> Self {
+        ReqwestTransport {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            http,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        ReqwestTransport::new(ONE_TWO_PAY_URL, reqwest::Client::new())
+    }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn post(&self, req: HttpRequest) -> Result<HttpResponse, TransportError> {
+        let mut authorization = HeaderValue::from_str(req.authorization.expose())
+            .map_err(|_| TransportError::NotSent("API key is not a valid header".to_owned()))?;
+        authorization.set_sensitive(true);
+        let mut builder = self
+            .http
+            .post(format!("{}{}", self.base_url, req.path))
+            .header("Content-Type", "application/json")
+            .header(AUTHORIZATION, authorization)
+            .body(req.body);
+        for (name, value) in req.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        let res = builder
+            .send()
+            .await
+            .map_err(|e| TransportError::Reqwest(Arc::new(e)))?;
+        let status = res.status().as_u16();
+        let body = res
+            .text()
+            .await
+            .map_err(|e| TransportError::Reqwest(Arc::new(e)))?;
+        Ok(HttpResponse { status, body })
+    }
+}
+
+/// In-memory transport that answers with scripted responses in FIFO order per path
+/// and remembers every request it received.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    responses: Mutex<HashMap<String, VecDeque<Result<HttpResponse, TransportError>>>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Script the next answer for the given path
+    pub fn push_response(&self, path: &str, response: Result<HttpResponse, TransportError>) {
+        self.responses
+            .lock()
+            .expect("mock lock")
+            .entry(path.to_owned())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// Script the next `/payout` answer with body in `TransferResInner` format
+    pub fn push_transfer(&self, body: serde_json::Value) {
+        self.push_response(
+            PAYOUT_PATH,
+            Ok(HttpResponse {
+                status: 200,
+                body: body.to_string(),
+            }),
+        );
+    }
+
+    /// Script the next `/inquery-trans` answer with body in `QueryResInner` format
+    pub fn push_query(&self, body: serde_json::Value) {
+        self.push_response(
+            QUERY_PATH,
+            Ok(HttpResponse {
+                status: 200,
+                body: body.to_string(),
+            }),
+        );
+    }
+
+    /// All requests received so far
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().expect("mock lock").clone()
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn post(&self, req: HttpRequest) -> Result<HttpResponse, TransportError> {
+        let path = req.path.clone();
+        self.requests.lock().expect("mock lock").push(req);
+        self.responses
+            .lock()
+            .expect("mock lock")
+            .get_mut(&path)
+            .and_then(|queue| queue.pop_front())
+            .unwrap_or(Err(TransportError::MockExhausted(path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiError;
+    use crate::fields::BankAccount;
+    use crate::money::Thb;
+    use crate::{Bank, Client, QueryReq, TransferReq};
+    use serde_json::json;
+
+    fn client(mock: Arc<MockTransport>) -> Client {
+        Client::with_transport("WEB", "CRS", "secret", mock)
+    }
+
+    #[tokio::test]
+    async fn mock_transfer_success() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_transfer(json!({
+            "status": 1000,
+            "message": "Success",
+            "payout_ref": "2022030288DtbRwK0IKr536t4",
+            "transaction_id": "2022030288DtbRwK0IKr536t4",
+            "transactionDate_time": "2023-09-20T17:35:13",
+        }));
+        let res = client(mock.clone())
+            .transfer(TransferReq {
+                bankacc: BankAccount::new(Bank::Kasikorn, "0652078409").expect("account"),
+                bank: Bank::Kasikorn,
+                accname: "Manop Tangngam".to_owned(),
+                amount: Thb::from_satang(100050),
+                mobileno: "0805933181".parse().expect("mobile"),
+                transaction_by: "Jack Developer".to_owned(),
+                ref1: "123456789012345678".parse().expect("ref1"),
+                ref2: None,
+                ref3: None,
+                ref4: None,
+                line_token: None,
+                email: None,
+                allow_duplicate: false,
+            })
+            .await
+            .expect("transfer");
+        assert_eq!(res.transaction_id, "2022030288DtbRwK0IKr536t4");
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, PAYOUT_PATH);
+        assert!(requests[0]
+            .headers
+            .contains(&("Partnercode".to_owned(), "CRS".to_owned())));
+        assert_eq!(requests[0].authorization.expose(), "secret");
+        assert!(!format!("{:?}", requests[0]).contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn error_keeps_response() {
+        let mock = Arc::new(MockTransport::new());
+        mock.push_response(
+            QUERY_PATH,
+            Ok(HttpResponse {
+                status: 502,
+                body: "<html>Bad Gateway</html>".to_owned(),
+            }),
+        );
+        mock.push_query(json!({"status": "9001", "message": "Temporarily unavailable"}));
+        let client = client(mock);
+        let query = || {
+            client.query(QueryReq {
+                ref1: "202205170841".to_owned(),
+            })
+        };
+
+        let err = query().await.expect_err("gateway failure");
+        let info = err.response().expect("response");
+        assert_eq!(info.status, 502);
+        assert_eq!(info.body, "<html>Bad Gateway</html>");
+
+        let err = query().await.expect_err("api failure");
+        assert_eq!(err.api_code(), Some(ApiError::ServiceUnavailable));
+        assert_eq!(
+            err.response().and_then(|r| r.message.as_deref()),
+            Some("Temporarily unavailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn mock_exhausted() {
+        let mock = Arc::new(MockTransport::new());
+        let res = client(mock)
+            .query(QueryReq {
+                ref1: "202205170841".to_owned(),
+            })
+            .await;
+        assert!(res.is_err());
+    }
+}
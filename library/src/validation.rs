@@ -0,0 +1,471 @@
+use std::fmt::Display;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::fields::{BankAccount, FieldError, Ref1, ThaiMobile};
+use crate::money::{MoneyError, Thb};
+use crate::{Bank, TransferReq};
+
+/// Max length of `ref2`, `ref3` and `ref4`, the same as for `ref1`
+pub const REF_MAX_LEN: usize = 30;
+
+/// Field of [`TransferReq`] a validation issue is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Bankacc,
+    Bank,
+    Accname,
+    Amount,
+    Mobileno,
+    TransactionBy,
+    Ref1,
+    Ref2,
+    Ref3,
+    Ref4,
+    Email,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Required field is not set or empty
+    Missing,
+    /// Value has wrong characters or structure
+    InvalidFormat,
+    /// Value is too short or too long
+    InvalidLength,
+    /// Amount is zero, negative or below configured minimum
+    AmountTooSmall,
+    /// Amount is above configured maximum
+    AmountTooLarge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ValidationIssue {
+    pub field: Field,
+    pub kind: IssueKind,
+    /// Human readable explanation
+    pub message: String,
+}
+
+/// All problems found in a transfer request, so they can be shown at once.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema, Error)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Issues of the given field
+    pub fn field(&self, field: Field) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(move |i| i.field == field)
+    }
+
+    pub(crate) fn push(&mut self, field: Field, kind: IssueKind, message: impl Display) {
+        self.issues.push(ValidationIssue {
+            field,
+            kind,
+            message: message.to_string(),
+        });
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Transfer request is invalid:")?;
+        for issue in self.issues.iter() {
+            write!(f, " {:?}: {};", issue.field, issue.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects raw values of [`TransferReq`] and validates all of them in
+/// [`TransferReqBuilder::build`].
+#[derive(Debug, Clone, Default)]
+pub struct TransferReqBuilder {
+    bankacc: Option<String>,
+    bank: Option<Result<Bank, String>>,
+    accname: Option<String>,
+    amount: Option<Result<Thb, MoneyError>>,
+    mobileno: Option<String>,
+    transaction_by: Option<String>,
+    ref1: Option<String>,
+    ref2: Option<String>,
+    ref3: Option<String>,
+    ref4: Option<String>,
+    line_token: Option<String>,
+    email: Option<String>,
+    min_amount: Option<Thb>,
+    max_amount: Option<Thb>,
+    allow_duplicate: bool,
+}
+
+impl TransferReqBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bankacc(mut self, bankacc: &str) -> Self {
+        self.bankacc = Some(bankacc.to_owned());
+        self
+    }
+
+    pub fn bank(mut self, bank: Bank) -> Self {
+        self.bank = Some(Ok(bank));
+        self
+    }
+
+    /// Bank as written in files, see [`Bank::parse`]. Example: "KBANK"
+    pub fn bank_str(mut self, bank: &str) -> Self {
+        self.bank = non_empty(Some(bank.to_owned()))
+            .map(|b| Bank::parse(&b).ok_or(format!("Unknown bank: {b}")));
+        self
+    }
+
+    pub fn accname(mut self, accname: &str) -> Self {
+        self.accname = Some(accname.to_owned());
+        self
+    }
+
+    pub fn amount(mut self, amount: Thb) -> Self {
+        self.amount = Some(Ok(amount));
+        self
+    }
+
+    /// Amount as user typed it. Example: "1,000.50"
+    pub fn amount_str(mut self, amount: &str) -> Self {
+        self.amount = Some(amount.parse());
+        self
+    }
+
+    pub fn mobileno(mut self, mobileno: &str) -> Self {
+        self.mobileno = Some(mobileno.to_owned());
+        self
+    }
+
+    pub fn transaction_by(mut self, transaction_by: &str) -> Self {
+        self.transaction_by = Some(transaction_by.to_owned());
+        self
+    }
+
+    pub fn ref1(mut self, ref1: &str) -> Self {
+        self.ref1 = Some(ref1.to_owned());
+        self
+    }
+
+    pub fn ref2(mut self, ref2: &str) -> Self {
+        self.ref2 = Some(ref2.to_owned());
+        self
+    }
+
+    pub fn ref3(mut self, ref3: &str) -> Self {
+        self.ref3 = Some(ref3.to_owned());
+        self
+    }
+
+    pub fn ref4(mut self, ref4: &str) -> Self {
+        self.ref4 = Some(ref4.to_owned());
+        self
+    }
+
+    pub fn line_token(mut self, line_token: &str) -> Self {
+        self.line_token = Some(line_token.to_owned());
+        self
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.email = Some(email.to_owned());
+        self
+    }
+
+    /// Reject amounts below the value. Any positive amount is accepted by default.
+    pub fn min_amount(mut self, amount: Thb) -> Self {
+        self.min_amount = Some(amount);
+        self
+    }
+
+    /// Reject amounts above the value. No limit by default.
+    pub fn max_amount(mut self, amount: Thb) -> Self {
+        self.max_amount = Some(amount);
+        self
+    }
+
+    /// See [`TransferReq::allow_duplicate`]
+    pub fn allow_duplicate(mut self, allow: bool) -> Self {
+        self.allow_duplicate = allow;
+        self
+    }
+
+    pub fn build(self) -> Result<TransferReq, ValidationReport> {
+        let mut report = ValidationReport::default();
+
+        let bank = match required(&mut report, Field::Bank, self.bank) {
+            Some(Ok(bank)) => Some(bank),
+            Some(Err(e)) => {
+                report.push(Field::Bank, IssueKind::InvalidFormat, e);
+                None
+            }
+            None => None,
+        };
+        let bankacc =
+            required(&mut report, Field::Bankacc, non_empty(self.bankacc)).and_then(|acc| {
+                match BankAccount::from_digits(&acc) {
+                    Ok(acc) => Some(acc),
+                    Err(e) => {
+                        report.push(Field::Bankacc, IssueKind::InvalidFormat, e);
+                        None
+                    }
+                }
+            });
+        if let (Some(bank), Some(acc)) = (bank, &bankacc) {
+            if let Err(e) = acc.check_bank(bank) {
+                report.push(Field::Bankacc, IssueKind::InvalidLength, e);
+            }
+        }
+        let accname = required(&mut report, Field::Accname, non_empty(self.accname));
+        let amount = match required(&mut report, Field::Amount, self.amount) {
+            Some(Ok(amount)) => check_amount(&mut report, amount, self.min_amount, self.max_amount),
+            Some(Err(e)) => {
+                report.push(Field::Amount, IssueKind::InvalidFormat, e);
+                None
+            }
+            None => None,
+        };
+        let mobileno =
+            required(&mut report, Field::Mobileno, non_empty(self.mobileno)).and_then(|m| {
+                match m.parse::<ThaiMobile>() {
+                    Ok(m) => Some(m),
+                    Err(e) => {
+                        report.push(Field::Mobileno, IssueKind::InvalidFormat, e);
+                        None
+                    }
+                }
+            });
+        let transaction_by = required(
+            &mut report,
+            Field::TransactionBy,
+            non_empty(self.transaction_by),
+        );
+        let ref1 = required(&mut report, Field::Ref1, non_empty(self.ref1)).and_then(|r| {
+            match r.parse::<Ref1>() {
+                Ok(r) => Some(r),
+                Err(e @ FieldError::RefLength(_)) => {
+                    report.push(Field::Ref1, IssueKind::InvalidLength, e);
+                    None
+                }
+                Err(e) => {
+                    report.push(Field::Ref1, IssueKind::InvalidFormat, e);
+                    None
+                }
+            }
+        });
+        let ref2 = check_ref(&mut report, Field::Ref2, self.ref2);
+        let ref3 = check_ref(&mut report, Field::Ref3, self.ref3);
+        let ref4 = check_ref(&mut report, Field::Ref4, self.ref4);
+        let email = non_empty(self.email);
+        if let Some(email) = &email {
+            if !is_email(email) {
+                report.push(
+                    Field::Email,
+                    IssueKind::InvalidFormat,
+                    format!("Not an email address: {email}"),
+                );
+            }
+        }
+
+        match (
+            bank,
+            bankacc,
+            accname,
+            amount,
+            mobileno,
+            transaction_by,
+            ref1,
+        ) {
+            (
+                Some(bank),
+                Some(bankacc),
+                Some(accname),
+                Some(amount),
+                Some(mobileno),
+                Some(transaction_by),
+                Some(ref1),
+            ) if report.is_empty() => Ok(TransferReq {
+                bankacc,
+                bank,
+                accname,
+                amount,
+                mobileno,
+                transaction_by,
+                ref1,
+                ref2,
+                ref3,
+                ref4,
+                line_token: non_empty(self.line_token),
+                email,
+                allow_duplicate: self.allow_duplicate,
+            }),
+            _ => Err(report),
+        }
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_owned()).filter(|v| !v.is_empty())
+}
+
+fn required<T>(report: &mut ValidationReport, field: Field, value: Option<T>) -> Option<T> {
+    if value.is_none() {
+        report.push(field, IssueKind::Missing, "Field is required");
+    }
+    value
+}
+
+fn check_amount(
+    report: &mut ValidationReport,
+    amount: Thb,
+    min: Option<Thb>,
+    max: Option<Thb>,
+) -> Option<Thb> {
+    if !amount.is_positive() {
+        report.push(
+            Field::Amount,
+            IssueKind::AmountTooSmall,
+            format!("Amount must be positive, got {amount}"),
+        );
+        return None;
+    }
+    if let Some(min) = min.filter(|min| amount < *min) {
+        report.push(
+            Field::Amount,
+            IssueKind::AmountTooSmall,
+            format!("Amount {amount} is below minimum {min}"),
+        );
+        return None;
+    }
+    if let Some(max) = max.filter(|max| amount > *max) {
+        report.push(
+            Field::Amount,
+            IssueKind::AmountTooLarge,
+            format!("Amount {amount} is above maximum {max}"),
+        );
+        return None;
+    }
+    Some(amount)
+}
+
+fn check_ref(report: &mut ValidationReport, field: Field, value: Option<String>) -> Option<String> {
+    let value = non_empty(value)?;
+    if value.chars().count() > REF_MAX_LEN {
+        report.push(
+            field,
+            IssueKind::InvalidLength,
+            format!("Must have length <= {REF_MAX_LEN}"),
+        );
+    }
+    Some(value)
+}
+
+/// Basic shape check: one '@', something before it and a dotted domain after it
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && !value.chars().any(char::is_whitespace)
+        && domain.split('.').filter(|part| !part.is_empty()).count() >= 2
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> TransferReqBuilder {
+        TransferReq::builder()
+            .bankacc("0652078409")
+            .bank(Bank::Kasikorn)
+            .accname("Manop Tangngam")
+            .amount_str("1,000.50")
+            .mobileno("+66 80 593 3181")
+            .transaction_by("Jack Developer")
+            .ref1("123456789012345678")
+    }
+
+    #[test]
+    fn valid_request() {
+        let req = valid().email("manop@example.com").build().expect("valid");
+        assert_eq!(req.amount, Thb::from_satang(100050));
+        assert_eq!(req.mobileno.as_str(), "0805933181");
+        assert_eq!(req.email.as_deref(), Some("manop@example.com"));
+        for bank in ["004", "kbank", "Kasikorn", "kasikorn"] {
+            assert_eq!(
+                valid().bank_str(bank).build().map(|r| r.bank),
+                Ok(Bank::Kasikorn)
+            );
+        }
+        let report = valid().bank_str("XYZ").build().expect_err("unknown bank");
+        assert_eq!(report.issues[0].field, Field::Bank);
+        assert_eq!(report.issues[0].kind, IssueKind::InvalidFormat);
+    }
+
+    #[test]
+    fn all_issues_reported() {
+        let report = TransferReq::builder()
+            .bankacc("66520784099")
+            .bank(Bank::Kasikorn)
+            .amount_str("0")
+            .mobileno("12345")
+            .transaction_by("Jack Developer")
+            .ref1("123456789012345678")
+            .ref2(&"x".repeat(31))
+            .email("manop@")
+            .build()
+            .expect_err("invalid");
+        let fields: Vec<(Field, IssueKind)> =
+            report.issues.iter().map(|i| (i.field, i.kind)).collect();
+        assert_eq!(
+            fields,
+            vec![
+                (Field::Bankacc, IssueKind::InvalidLength),
+                (Field::Accname, IssueKind::Missing),
+                (Field::Amount, IssueKind::AmountTooSmall),
+                (Field::Mobileno, IssueKind::InvalidFormat),
+                (Field::Ref2, IssueKind::InvalidLength),
+                (Field::Email, IssueKind::InvalidFormat),
+            ]
+        );
+        let json = serde_json::to_value(&report).expect("encoded");
+        assert_eq!(json["issues"][0]["field"], "bankacc");
+        assert_eq!(json["issues"][0]["kind"], "invalid_length");
+    }
+
+    #[test]
+    fn amount_limits() {
+        let report = valid()
+            .max_amount(Thb::from_baht(1000).expect("thb"))
+            .build()
+            .expect_err("too large");
+        assert_eq!(report.issues[0].kind, IssueKind::AmountTooLarge);
+        let report = valid().amount_str("1.005").build().expect_err("sub satang");
+        assert_eq!(report.issues[0].kind, IssueKind::InvalidFormat);
+    }
+
+    #[test]
+    fn email_shape() {
+        assert!(is_email("a@b.io"));
+        assert!(!is_email("a@b"));
+        assert!(!is_email("a b@c.io"));
+        assert!(!is_email("@c.io"));
+        assert!(!is_email("a@b@c.io"));
+    }
+}
@@ -0,0 +1,513 @@
+//! Fake 1-2-Pay server for offline integration tests. It speaks the same headers and
+//! JSON shapes as the real `/payout` and `/inquery-trans` endpoints and keeps all
+//! transactions in memory.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{NaiveDateTime, Utc};
+use log::*;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+/// Status code the mock answers with by default when inquery gets unknown ref1.
+/// The provider doesn't document the real one, so clients have to be configured
+/// with it, see `ClientBuilder::not_found_code`.
+pub const NOT_FOUND_CODE: i32 = -1010;
+
+/// What the server does with a matching transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// 1000, money is transferred immediately
+    Success,
+    /// 9090, no response from the bank and it never comes
+    Pending,
+    /// -1003, duplicate transaction
+    Duplicate,
+    /// -1009, balance is not enough
+    InsufficientBalance,
+    /// 9090 first, then inquery returns success after the delay passes
+    DelayedSuccess(Duration),
+    /// Any other code the server should fail with
+    Code(i32),
+}
+
+impl Outcome {
+    fn initial_code(self) -> i32 {
+        match self {
+            Outcome::Success => 1000,
+            Outcome::Pending | Outcome::DelayedSuccess(_) => 9090,
+            Outcome::Duplicate => -1003,
+            Outcome::InsufficientBalance => -1009,
+            Outcome::Code(c) => c,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Unknown outcome '{0}', expected one of: success, pending, duplicate, insufficient-balance, delayed:<secs>, code:<int>")]
+pub struct OutcomeParseError(String);
+
+impl FromStr for Outcome {
+    type Err = OutcomeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || OutcomeParseError(s.to_owned());
+        match s {
+            "success" => Ok(Outcome::Success),
+            "pending" => Ok(Outcome::Pending),
+            "duplicate" => Ok(Outcome::Duplicate),
+            "insufficient-balance" => Ok(Outcome::InsufficientBalance),
+            _ => {
+                if let Some(secs) = s.strip_prefix("delayed:") {
+                    let secs = secs.parse().map_err(|_| err())?;
+                    Ok(Outcome::DelayedSuccess(Duration::from_secs(secs)))
+                } else if let Some(code) = s.strip_prefix("code:") {
+                    Ok(Outcome::Code(code.parse().map_err(|_| err())?))
+                } else {
+                    Err(err())
+                }
+            }
+        }
+    }
+}
+
+/// Which transfers a scripted outcome applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Matcher {
+    Ref1(String),
+    /// Amount in satang, e.g. 1000.50 THB is 100050
+    Amount(i64),
+}
+
+impl Matcher {
+    fn matches(&self, ref1: &str, amount: i64) -> bool {
+        match self {
+            Matcher::Ref1(r) => r == ref1,
+            Matcher::Amount(a) => *a == amount,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Unknown script '{0}', expected ref1:<ref1>=<outcome> or amount:<thb>=<outcome>")]
+pub struct ScriptParseError(String);
+
+/// Parse scripts in form `ref1:<ref1>=<outcome>` or `amount:<thb>=<outcome>`
+pub fn parse_script(s: &str) -> Result<(Matcher, Outcome), ScriptParseError> {
+    let err = || ScriptParseError(s.to_owned());
+    let (matcher, outcome) = s.rsplit_once('=').ok_or_else(err)?;
+    let outcome = outcome.parse().map_err(|_| err())?;
+    let matcher = if let Some(ref1) = matcher.strip_prefix("ref1:") {
+        Matcher::Ref1(ref1.to_owned())
+    } else if let Some(amount) = matcher.strip_prefix("amount:") {
+        Matcher::Amount(to_satang(amount.parse().map_err(|_| err())?))
+    } else {
+        return Err(err());
+    };
+    Ok((matcher, outcome))
+}
+
+#[derive(Debug, Clone)]
+struct Transaction {
+    status: i32,
+    ready_at: Option<Instant>,
+    accname: String,
+    bankacc: String,
+    bankcode: String,
+    amount: i64,
+    ref1: String,
+    ref2: String,
+    ref3: String,
+    ref4: String,
+    created_date: NaiveDateTime,
+    transfer_date: Option<NaiveDateTime>,
+    transaction_id: String,
+}
+
+impl Transaction {
+    /// Applies delayed success if its time has come
+    fn refresh(&mut self) {
+        if let Some(ready_at) = self.ready_at {
+            if Instant::now() >= ready_at {
+                self.status = 1000;
+                self.ready_at = None;
+                self.transfer_date = Some(bangkok_now());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    credentials: Option<Credentials>,
+    scripts: Vec<(Matcher, Outcome)>,
+    transactions: HashMap<String, Transaction>,
+    counter: u64,
+    not_found_code: Option<i32>,
+}
+
+/// Values of the `Authorization`, `Partnercode` and `Channel` headers the server accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub api_key: String,
+    pub partner_code: String,
+    pub channel: String,
+}
+
+/// Fake 1-2-Pay server. Clones share the same transaction store, so tests can keep a
+/// handle to script outcomes while the server runs.
+#[derive(Debug, Clone, Default)]
+pub struct MockServer {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject requests with other auth headers with -1002. By default any non empty
+    /// values are accepted.
+    pub fn with_credentials(self, credentials: Credentials) -> Self {
+        self.lock().credentials = Some(credentials);
+        self
+    }
+
+    /// Answer inquery of unknown ref1 with this status. Default: [`NOT_FOUND_CODE`]
+    pub fn with_not_found_code(self, code: i32) -> Self {
+        self.lock().not_found_code = Some(code);
+        self
+    }
+
+    /// Transfers with the ref1 end with the outcome. Later scripts take precedence.
+    pub fn script_ref1(&self, ref1: &str, outcome: Outcome) {
+        self.script(Matcher::Ref1(ref1.to_owned()), outcome);
+    }
+
+    /// Transfers with the amount end with the outcome. Later scripts take precedence.
+    pub fn script_amount(&self, amount: f64, outcome: Outcome) {
+        self.script(Matcher::Amount(to_satang(amount)), outcome);
+    }
+
+    pub fn script(&self, matcher: Matcher, outcome: Outcome) {
+        self.lock().scripts.push((matcher, outcome));
+    }
+
+    /// Number of transactions stored so far
+    pub fn transaction_count(&self) -> usize {
+        self.lock().transactions.len()
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/payout", post(payout))
+            .route("/inquery-trans", post(inquery))
+            .with_state(self.clone())
+    }
+
+    /// Serve on the given address until the future is dropped
+    pub async fn serve(self, addr: SocketAddr) -> Result<(), hyper::Error> {
+        axum::Server::bind(&addr)
+            .serve(self.router().into_make_service())
+            .await
+    }
+
+    /// Start serving on a random local port in background. Returns base URL for the client.
+    pub fn spawn(&self) -> String {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(self.router().into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock state lock")
+    }
+}
+
+fn to_satang(amount: f64) -> i64 {
+    (amount * 100.0).round() as i64
+}
+
+/// Format satang as provider does: "100,001.00"
+fn format_amount(satang: i64) -> String {
+    let whole = (satang / 100).to_string();
+    let mut grouped = String::new();
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    format!("{}.{:02}", grouped, satang % 100)
+}
+
+fn bangkok_now() -> NaiveDateTime {
+    Utc::now().naive_utc() + chrono::Duration::hours(7)
+}
+
+fn message(code: i32) -> impl Display {
+    match code {
+        1000 => "Success",
+        -1001 => "Invalid json request",
+        -1002 => "Invalid Authorization",
+        -1003 => "Duplicate Transaction",
+        -1009 => "Balance is not enough",
+        9090 => "No response data from the bank. Please wait for us to query and update shortly",
+        _ => "Transaction failed",
+    }
+}
+
+fn failure(code: i32) -> Json<Value> {
+    Json(json!({
+        "status": code,
+        "message": message(code).to_string(),
+    }))
+}
+
+fn authorized(state: &MockState, headers: &HeaderMap) -> bool {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned()
+    };
+    let given = Credentials {
+        api_key: header("Authorization"),
+        partner_code: header("Partnercode"),
+        channel: header("Channel"),
+    };
+    match &state.credentials {
+        Some(expected) => *expected == given,
+        None => {
+            !given.api_key.is_empty() && !given.partner_code.is_empty() && !given.channel.is_empty()
+        }
+    }
+}
+
+fn field(body: &Value, name: &str) -> Option<String> {
+    body.get(name)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_owned())
+}
+
+async fn payout(State(server): State<MockServer>, headers: HeaderMap, body: String) -> Json<Value> {
+    let mut state = server.lock();
+    if !authorized(&state, &headers) {
+        return failure(-1002);
+    }
+    let body: Value = match serde_json::from_str(&body) {
+        Ok(v) => v,
+        Err(_) => return failure(-1001),
+    };
+    trace!("Payout: {body}");
+    let (Some(ref1), Some(bankacc), Some(bankcode), Some(accname), Some(amount)) = (
+        field(&body, "ref1"),
+        field(&body, "bankacc"),
+        field(&body, "bankcode"),
+        field(&body, "accname"),
+        body.get("amount").and_then(|v| v.as_f64()),
+    ) else {
+        return failure(-1001);
+    };
+    if state.transactions.contains_key(&ref1) {
+        return failure(-1003);
+    }
+    let amount = to_satang(amount);
+    let outcome = state
+        .scripts
+        .iter()
+        .rev()
+        .find(|(m, _)| m.matches(&ref1, amount))
+        .map(|(_, o)| *o)
+        .unwrap_or(Outcome::Success);
+    if outcome == Outcome::Duplicate {
+        return failure(-1003);
+    }
+
+    state.counter += 1;
+    let now = bangkok_now();
+    let transaction_id = format!("{}MOCK{:011}", now.format("%Y%m%d"), state.counter);
+    let status = outcome.initial_code();
+    let tx = Transaction {
+        status,
+        ready_at: match outcome {
+            Outcome::DelayedSuccess(delay) => Some(Instant::now() + delay),
+            _ => None,
+        },
+        accname,
+        bankacc,
+        bankcode,
+        amount,
+        ref1: ref1.clone(),
+        ref2: field(&body, "ref2").unwrap_or_default(),
+        ref3: field(&body, "ref3").unwrap_or_default(),
+        ref4: field(&body, "ref4").unwrap_or_default(),
+        created_date: now,
+        transfer_date: if status == 1000 { Some(now) } else { None },
+        transaction_id: transaction_id.clone(),
+    };
+    state.transactions.insert(ref1, tx);
+
+    if status == 1000 {
+        Json(json!({
+            "status": status,
+            "message": message(status).to_string(),
+            "payout_ref": transaction_id,
+            "transaction_id": transaction_id,
+            "transactionDate_time": now.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        }))
+    } else {
+        failure(status)
+    }
+}
+
+async fn inquery(
+    State(server): State<MockServer>,
+    headers: HeaderMap,
+    body: String,
+) -> Json<Value> {
+    let mut state = server.lock();
+    if !authorized(&state, &headers) {
+        return failure(-1002);
+    }
+    let Some(ref1) = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|b| field(&b, "ref1"))
+    else {
+        return failure(-1001);
+    };
+    let not_found = state.not_found_code.unwrap_or(NOT_FOUND_CODE);
+    let Some(tx) = state.transactions.get_mut(&ref1) else {
+        return Json(json!({
+            "status": not_found,
+            "message": "Transaction not found",
+        }));
+    };
+    tx.refresh();
+
+    let format_date = |d: NaiveDateTime| d.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+    let mut res = json!({
+        "status": tx.status.to_string(),
+        "message": message(tx.status).to_string(),
+        "accname": tx.accname,
+        "bankacc": tx.bankacc,
+        "bankcode": tx.bankcode,
+        "amount": format_amount(tx.amount),
+        "ref1": tx.ref1,
+        "ref2": tx.ref2,
+        "ref3": tx.ref3,
+        "ref4": tx.ref4,
+        "created_date": format_date(tx.created_date),
+    });
+    if let Some(transfer_date) = tx.transfer_date {
+        res["transfer_date"] = json!(format_date(transfer_date));
+        res["transfer_transactionId"] = json!(tx.transaction_id);
+    }
+    Json(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use one_two_pay_api::error::{ApiError, Error};
+    use one_two_pay_api::fields::BankAccount;
+    use one_two_pay_api::{Bank, Client, QueryReq, TransferReq};
+
+    fn transfer_req(ref1: &str, amount: &str) -> TransferReq {
+        TransferReq {
+            bankacc: BankAccount::new(Bank::Kasikorn, "0652078409").expect("account"),
+            bank: Bank::Kasikorn,
+            accname: "Manop Tangngam".to_owned(),
+            amount: amount.parse().expect("amount"),
+            mobileno: "0805933181".parse().expect("mobile"),
+            transaction_by: "Jack Developer".to_owned(),
+            ref1: ref1.parse().expect("ref1"),
+            ref2: None,
+            ref3: None,
+            ref4: None,
+            line_token: None,
+            email: None,
+            allow_duplicate: false,
+        }
+    }
+
+    #[test]
+    fn amount_formatting() {
+        assert_eq!(format_amount(100), "1.00");
+        assert_eq!(format_amount(10000100), "100,001.00");
+        assert_eq!(format_amount(123456789), "1,234,567.89");
+    }
+
+    #[test]
+    fn script_parsing() {
+        assert_eq!(
+            parse_script("ref1:abc=pending"),
+            Ok((Matcher::Ref1("abc".to_owned()), Outcome::Pending))
+        );
+        assert_eq!(
+            parse_script("amount:1000.50=delayed:5"),
+            Ok((
+                Matcher::Amount(100050),
+                Outcome::DelayedSuccess(Duration::from_secs(5))
+            ))
+        );
+        assert!(parse_script("amount:abc=success").is_err());
+    }
+
+    #[tokio::test]
+    async fn transfer_then_query() {
+        let server = MockServer::new();
+        let url = server.spawn();
+        let client = Client::builder("WEB", "CRS", "key")
+            .base_url(&url)
+            .build()
+            .expect("client");
+
+        let res = client
+            .transfer(transfer_req("202205170841", "1000.50"))
+            .await
+            .expect("transfer");
+        let query = client
+            .query(QueryReq {
+                ref1: "202205170841".to_owned(),
+            })
+            .await
+            .expect("query");
+        let query = format!("{query:?}");
+        assert!(query.contains(&res.transaction_id));
+        assert!(query.contains("amount: Thb(1000.50)"));
+        assert_eq!(server.transaction_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn scripted_outcomes() {
+        let server = MockServer::new();
+        server.script_ref1("pending", Outcome::Pending);
+        server.script_amount(500.0, Outcome::InsufficientBalance);
+        let url = server.spawn();
+        let client = Client::builder("WEB", "CRS", "key")
+            .base_url(&url)
+            .build()
+            .expect("client");
+
+        let code = |res: Result<_, Error>| res.err().and_then(|e| e.api_code());
+        let pending = client.transfer(transfer_req("pending", "10")).await;
+        assert_eq!(code(pending), Some(ApiError::WaitingBankResponse));
+        let poor = client.transfer(transfer_req("poor", "500")).await;
+        assert_eq!(code(poor), Some(ApiError::InsufficientBalance));
+        let dup = client.transfer(transfer_req("poor", "10")).await;
+        assert_eq!(code(dup), Some(ApiError::DuplicateTransaction));
+    }
+}
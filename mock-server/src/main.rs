@@ -0,0 +1,55 @@
+use std::net::SocketAddr;
+
+use clap::Parser;
+use one_two_pay_mock::{parse_script, Credentials, MockServer, NOT_FOUND_CODE};
+
+#[derive(Parser)]
+#[command(author, version, about = "Fake 1-2-Pay server for offline testing", long_about = None)]
+struct Cli {
+    /// Address to listen on
+    #[arg(short, long, env = "LISTEN", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Accept only this API key. Any non empty key is accepted if not set.
+    #[arg(short, long, env = "API_KEY", hide_env_values = true, requires_all = ["partner_code", "channel"])]
+    api_key: Option<String>,
+
+    #[arg(short, long, env = "PARTNER_CODE")]
+    partner_code: Option<String>,
+
+    #[arg(short, long, env = "CHANNEL")]
+    channel: Option<String>,
+
+    /// Scripted outcome in form `ref1:<ref1>=<outcome>` or `amount:<thb>=<outcome>`,
+    /// where outcome is one of: success, pending, duplicate, insufficient-balance,
+    /// delayed:<secs>, code:<int>
+    #[arg(short, long, value_parser = parse_script)]
+    script: Vec<(one_two_pay_mock::Matcher, one_two_pay_mock::Outcome)>,
+
+    /// Status of inquery for unknown ref1, clients need the same code
+    #[arg(long, env = "NOT_FOUND_CODE", default_value_t = NOT_FOUND_CODE, allow_hyphen_values = true)]
+    not_found_code: i32,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let cli = Cli::parse();
+    let mut server = MockServer::new().with_not_found_code(cli.not_found_code);
+    if let (Some(api_key), Some(partner_code), Some(channel)) =
+        (cli.api_key, cli.partner_code, cli.channel)
+    {
+        server = server.with_credentials(Credentials {
+            api_key,
+            partner_code,
+            channel,
+        });
+    }
+    for (matcher, outcome) in cli.script {
+        server.script(matcher, outcome);
+    }
+    println!("Listening on http://{}", cli.listen);
+    server.serve(cli.listen).await?;
+    Ok(())
+}